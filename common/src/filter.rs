@@ -1,6 +1,7 @@
 use std::fmt;
 use crate::img::{self, Image};
 use crate::url;
+use crate::timeout::Steps;
 use std::fmt::Display;
 use std::str::FromStr;
 use hyper;
@@ -9,6 +10,7 @@ use tokio_core::reactor::Remote;
 use failure::{Fail, Error};
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::cell::RefCell;
 
 #[derive(Debug,Clone,Eq,PartialEq)]
 pub enum SizeUnit {
@@ -19,7 +21,9 @@ pub enum SizeUnit {
     HorizontalCentimeters,
     VerticalCentimeters,
     HorizontalInches,
-    VerticalInches
+    VerticalInches,
+    /// Multiplied by the device pixel ratio from client hints.
+    Dpr
 }
 
 #[derive(PartialEq,Eq,Debug,Fail)]
@@ -38,11 +42,28 @@ impl FromStr for SizeUnit {
             "vcm" => SizeUnit::VerticalCentimeters,
             "hin" => SizeUnit::HorizontalInches,
             "vin" => SizeUnit::VerticalInches,
+            "dpr" => SizeUnit::Dpr,
             _ => return Err(UnknownSizeUnit(input.to_owned()))
         })
     }
 }
 
+impl Display for SizeUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            SizeUnit::None => "",
+            SizeUnit::Px => "px",
+            SizeUnit::Width => "w",
+            SizeUnit::Height => "h",
+            SizeUnit::HorizontalCentimeters => "hcm",
+            SizeUnit::VerticalCentimeters => "vcm",
+            SizeUnit::HorizontalInches => "hin",
+            SizeUnit::VerticalInches => "vin",
+            SizeUnit::Dpr => "dpr",
+        })
+    }
+}
+
 #[derive(Debug,Clone)]
 pub enum FilterArg {
     Int(isize, SizeUnit),
@@ -61,8 +82,11 @@ pub struct Filter {
 impl Display for FilterArg {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            FilterArg::Int(ref v, ref u) => write!(f, "{}{:?}", v, u),
-            FilterArg::Float(ref v, ref u) => write!(f, "{}{:?}", v, u),
+            FilterArg::Int(ref v, ref u) => write!(f, "{}{}", v, u),
+            // The decimal point has to be kept, so that the value is parsed as a float again,
+            // and Display (unlike Debug) never switches to exponent notation.
+            FilterArg::Float(ref v, ref u) if v.fract() == 0.0 => write!(f, "{}.0{}", v, u),
+            FilterArg::Float(ref v, ref u) => write!(f, "{}{}", v, u),
             FilterArg::String(ref v) => write!(f, "{}", v),
             FilterArg::Img(ref v) => write!(f, "{}", v),
             FilterArg::ResolvedImg(_) => unimplemented!()
@@ -115,6 +139,29 @@ impl FilterResult for ErrorResponse {
     fn image(self: Box<Self>) -> Result<img::Image, Error> { self.0.image() }
 }
 
+/// A plain-text message returned to the client with an error status.
+#[derive(Debug, Clone)]
+pub struct StatusMessage(pub hyper::StatusCode, pub String);
+
+impl FilterResult for StatusMessage {
+    fn content_type(&self) -> Result<hyper::header::ContentType, Error> {
+        Ok(hyper::header::ContentType::plaintext())
+    }
+
+    fn status_code(&self) -> hyper::StatusCode {
+        self.0
+    }
+
+    fn content(&self) -> Result<Rc<Vec<u8>>, Error> {
+        Ok(Rc::new(self.1.clone().into_bytes()))
+    }
+}
+
+/// An error which is returned to the client as `message` with `status`.
+pub fn error_response(status: hyper::StatusCode, message: String) -> Error {
+    Error::from(ErrorResponse(Box::new(StatusMessage(status, message))))
+}
+
 impl<T: FilterResult + 'static> From<Box<T>> for Box<FilterResult> {
     fn from(obj: Box<T>) -> Self {
         obj
@@ -133,9 +180,60 @@ pub type FilterMap = HashMap<&'static str, &'static (Fn(&mut Context, &Args) ->
 #[derive(Clone)]
 pub struct Context {
     pub filters: &'static FilterMap,
+    /// Filters which can only be used in trees built by a plugin (e.g. a front-end),
+    /// and not in urls.
+    pub private_filters: Option<&'static FilterMap>,
+    pub aliases: &'static HashMap<String, String>,
+    pub allow_builtin_filters: bool,
     pub remote: Remote,
+    pub request_headers: Rc<hyper::Headers>,
     pub log_filters_header: &'static Option<String>,
-    pub response_headers: HashMap<String, String>
+    /// Filters which are still running, reported when the request deadline passes.
+    pub steps: Steps,
+    /// Entries of the `log_filters_header`, one per executed filter.
+    pub filter_log: FilterLog,
+    /// Shared between clones of the context, so that filters can add headers
+    /// after their future resolves.
+    pub response_headers: Rc<RefCell<HashMap<String, String>>>
+}
+
+/// Shared between clones of a context, so that filters can amend their entries
+/// after their future resolves.
+#[derive(Clone, Default)]
+pub struct FilterLog(Rc<RefCell<Vec<String>>>);
+
+impl FilterLog {
+    pub fn push(&self, entry: String) -> usize {
+        let mut entries = self.0.borrow_mut();
+        entries.push(entry);
+        entries.len() - 1
+    }
+
+    /// Appends `details` to the entry at `index`. Entries never move, so indices stay valid.
+    pub fn append(&self, index: usize, details: &str) {
+        if let Some(entry) = self.0.borrow_mut().get_mut(index) {
+            entry.push_str(details);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn entries(&self, range: ::std::ops::Range<usize>) -> Vec<String> {
+        self.0.borrow()[range].to_vec()
+    }
+}
+
+impl Context {
+    /// Appends `details` to the log entry of the filter which was started last, i.e. the one
+    /// calling this before starting its inputs. Returns the entry's index if filters are logged.
+    pub fn log_details(&self, details: &str) -> Option<usize> {
+        self.log_filters_header.as_ref()?;
+        let index = self.filter_log.len().checked_sub(1)?;
+        self.filter_log.append(index, details);
+        Some(index)
+    }
 }
 
 pub fn parse_size<T: Into<f32>>(val: T, unit: &SizeUnit, img: &Image) -> Result<f32, Error> {
@@ -148,6 +246,7 @@ pub fn parse_size<T: Into<f32>>(val: T, unit: &SizeUnit, img: &Image) -> Result<
             SizeUnit::VerticalCentimeters => (val * 0.3937008 * img.resolution()?.1 as f32),
             SizeUnit::HorizontalInches => (val * img.resolution()?.0 as f32),
             SizeUnit::VerticalInches => (val * img.resolution()?.1 as f32),
+            SizeUnit::Dpr => bail!("The dpr unit is only supported by resizing filters."),
         })
 }
 
@@ -269,6 +368,8 @@ impl ArgTypeImg for img::ResolutionUnit {}
 impl ArgTypeImg for img::ImageFormat {}
 impl ArgTypeImg for img::AlphaChannel {}
 impl ArgTypeImg for img::Gravity {}
+impl ArgTypeImg for img::NoiseType {}
+impl ArgTypeImg for img::Metric {}
 
 #[macro_export]
 macro_rules! arg_type {
@@ -409,32 +510,99 @@ pub fn exec_from_partial_url(context: &mut Context, img: Box<img::Image>, url: &
     }))
 }
 
-fn log_filter(context: &mut Context, filter: &Filter) -> Result<(), Error> {
-    let header_name = if let Some(ref name) = context.log_filters_header {
-        name
-    } else {
-        return Ok(())
-    };
+/// Returns the index of the alias argument referenced by `arg` (e.g. `{1}`), if any.
+fn alias_arg(arg: &str) -> Option<usize> {
+    let start = arg.find('{')?;
+    let end = start + arg[start..].find('}')?;
+    arg[start + 1..end].parse().ok()
+}
 
-    let mut route = String::new();
-    let headers = &mut context.response_headers;
-    if let Some(val) = headers.get(header_name) {
-        route.push_str(val);
+fn apply_alias_args(mut filter: Filter, args: &Args) -> Result<Filter, Error> {
+    let mut new_args = Vec::with_capacity(filter.args.len());
+    for arg in filter.args.into_iter() {
+        match arg {
+            FilterArg::Img(filter) => new_args.push(FilterArg::Img(apply_alias_args(filter, args)?)),
+            FilterArg::String(ref s) => if let Some(arg_num) = alias_arg(s) {
+                if arg_num >= args.len() {
+                    bail!("Not enough arguments passed to {}. Expected at least {}, but you passed {}.", filter.name, arg_num+1, args.len());
+                }
+                new_args.push(args[arg_num].clone())
+            } else {
+                new_args.push(arg.clone())
+            },
+            _ => new_args.push(arg)
+        }
     }
-    if route.len() > 0 {
-        route.push_str(",")
+    filter.args = new_args;
+    Ok(filter)
+}
+
+/// Replaces aliases in `filter` with their definitions, and rejects builtin filters
+/// if they aren't allowed. This has to be done for every filter tree coming from a client.
+pub fn apply_aliases(context: &Context, mut filter: Filter) -> Result<Filter, Error> {
+    let mut new_args = Vec::with_capacity(filter.args.len());
+    for arg in filter.args.into_iter() {
+        match arg {
+            FilterArg::Img(filter) => new_args.push(FilterArg::Img(apply_aliases(context, filter)?)),
+            _ => new_args.push(arg)
+        }
+    }
+    filter.args = new_args;
+    if let Some(value) = context.aliases.get(&filter.name) {
+        apply_alias_args(url::parse(value)?, &filter.args)
+    } else if context.allow_builtin_filters {
+        Ok(filter)
+    } else {
+        bail!("no such filter: {}", filter.name);
+    }
+}
+
+fn log_filter(context: &mut Context, filter: &Filter) -> Result<(), Error> {
+    if context.log_filters_header.is_some() {
+        context.filter_log.push(filter.name.clone());
     }
-    route.push_str(filter.name.as_str());
-    headers.insert(header_name.to_owned(), route);
     Ok(())
 }
 
+/// Turns the result of a filter into a response, including the headers set by filters.
+pub fn into_response(context: &Context, result: Box<FilterResult>) -> Result<hyper::Response, Error> {
+    let content_type = result.content_type()?;
+    let body = result.content()?;
+    // Release the result, so that it doesn't hold another reference to the body.
+    drop(result);
+    let mut response = hyper::Response::new()
+        .with_header(hyper::header::ContentLength(body.len() as u64))
+        .with_header(content_type);
+    {
+        let response_headers = response.headers_mut();
+        for (name, value) in context.response_headers.borrow().iter() {
+            response_headers.set_raw(name.clone(), value.as_str());
+        }
+        if let Some(ref header_name) = *context.log_filters_header {
+            let entries = context.filter_log.entries(0..context.filter_log.len());
+            if !entries.is_empty() {
+                response_headers.set_raw(header_name.clone(), entries.join(","));
+            }
+        }
+    }
+    Ok(response.with_body(Rc::try_unwrap(body).unwrap_or_else(|body| (*body).clone())))
+}
+
 pub fn exec_filter(context: &mut Context, filter: &Filter) -> Box<Future> {
     if let Err(e) = log_filter(context, filter) {
         return Box::new(future::err(e));
     }
-    match context.filters.get(filter.name.as_str()) {
-        Some(f) => (f)(context, &filter.args),
-        None => Box::new(future::err(format_err!("no such filter: {}", filter.name)))
-    }
+    let private = context.private_filters.and_then(|filters| filters.get(filter.name.as_str()));
+    let f = match private.or_else(|| context.filters.get(filter.name.as_str())) {
+        Some(f) => f,
+        None => return Box::new(future::err(format_err!("no such filter: {}", filter.name)))
+    };
+    // Started before the filter runs, because it starts its inputs right away.
+    let id = context.steps.start(filter.name.clone());
+    let steps = context.steps.clone();
+    let future = (f)(context, &filter.args);
+    Box::new(future.then(move |result| {
+        steps.finish(id);
+        result
+    }))
 }
@@ -0,0 +1,142 @@
+//! Helpers for plugins that translate other url syntaxes into filter trees.
+use hyper;
+use futures::{future, Future as FutureTrait};
+use failure::Error;
+use crate::filter::{self, Context, Filter, FilterArg, error_response, exec_filter};
+
+/// An error which is returned to the client as 400 Bad Request.
+pub fn bad_request(message: String) -> Error {
+    error_response(hyper::StatusCode::BadRequest, message)
+}
+
+/// Returns the part of `path` after `prefix`, if `path` is `prefix` itself or lies below it.
+/// `/iiif` matches `/iiif` and `/iiif/...`, but not `/iiifx/...`.
+pub fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    if !path.starts_with(prefix) {
+        return None;
+    }
+    let rest = &path[prefix.len()..];
+    if prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+/// Returns a filter taking `img` as its first argument, followed by `args`.
+pub fn apply(img: Filter, name: &str, mut args: Vec<FilterArg>) -> Filter {
+    args.insert(0, FilterArg::Img(img));
+    Filter { name: name.to_owned(), args }
+}
+
+/// Executes `filter` and returns its result as a middleware response.
+/// If `cache` is given, the result is stored in the cache with that name.
+///
+/// The tree is built by the front-end from its own grammar, so it is executed as it is:
+/// aliases aren't substituted into it and `allow_builtin_filters` doesn't apply. Which
+/// front-ends are exposed is decided by configuring them.
+pub fn respond(mut context: Context, filter: Filter, cache: Option<&String>) -> Box<FutureTrait<Item=Option<hyper::Response>, Error=Error>> {
+    let filter = match cache {
+        Some(cache) => apply(filter, "cache", vec![FilterArg::String(cache.clone())]),
+        None => filter
+    };
+    Box::new(exec_filter(&mut context, &filter).and_then(move |result| {
+        filter::into_response(&context, result).map(Some)
+    }))
+}
+
+/// Middleware body shared by front-ends mounted at `prefix`: requests outside of it are
+/// passed on to the next middleware, the rest are translated by `parse` and executed.
+pub fn serve<F>(request: &hyper::Request, prefix: &str, context: Context, cache: Option<&String>, parse: F) -> Box<FutureTrait<Item=Option<hyper::Response>, Error=Error>>
+    where F: FnOnce(&str) -> Result<Filter, Error>
+{
+    let path = match strip_prefix(request.path(), prefix) {
+        Some(path) => path,
+        None => return Box::new(future::ok(None))
+    };
+    match parse(path) {
+        Ok(filter) => respond(context, filter, cache),
+        Err(e) => Box::new(future::err(e))
+    }
+}
+
+/// Names of the filters in the chain ending at `filter`, starting from the source,
+/// following the first argument of each filter.
+pub fn filter_chain(filter: &Filter) -> Vec<String> {
+    let mut names = match filter.args.get(0) {
+        Some(&FilterArg::Img(ref inner)) => filter_chain(inner),
+        _ => vec![]
+    };
+    names.push(filter.name.clone());
+    names
+}
+
+#[test]
+fn test_strip_prefix() {
+    assert_eq!(strip_prefix("/iiif/abc/info.json", "/iiif"), Some("/abc/info.json"));
+    assert_eq!(strip_prefix("/iiif", "/iiif"), Some(""));
+    assert_eq!(strip_prefix("/iiif/abc", "/iiif/"), Some("abc"));
+    assert_eq!(strip_prefix("/iiifx/abc", "/iiif"), None);
+    assert_eq!(strip_prefix("/other", "/iiif"), None);
+}
+
+#[test]
+fn test_respond_ignores_aliases() {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use futures::Stream;
+    use tokio_core::reactor::Core;
+    use crate::filter::{Args, FilterMap, FilterResult, Future};
+
+    #[derive(Debug)]
+    struct Text(&'static str);
+
+    impl FilterResult for Text {
+        fn content_type(&self) -> Result<hyper::header::ContentType, Error> {
+            Ok(hyper::header::ContentType::plaintext())
+        }
+
+        fn content(&self) -> Result<Rc<Vec<u8>>, Error> {
+            Ok(Rc::new(self.0.as_bytes().to_vec()))
+        }
+    }
+
+    fn source(_: &mut Context, _: &Args) -> Box<Future> {
+        Box::new(future::ok::<Box<FilterResult>, Error>(Box::new(Text("source"))))
+    }
+
+    fn crop(context: &mut Context, args: &Args) -> Box<Future> {
+        match args.get(0) {
+            Some(&FilterArg::Img(ref img)) => Box::new(exec_filter(context, img).map(|_| -> Box<FilterResult> { Box::new(Text("crop")) })),
+            _ => Box::new(future::err(format_err!("crop needs an image")))
+        }
+    }
+
+    let mut filters: FilterMap = HashMap::new();
+    filters.insert("source", &source);
+    filters.insert("crop", &crop);
+    // An alias shadowing a builtin, which must not leak into the front-end tree.
+    let mut aliases = HashMap::new();
+    aliases.insert("crop".to_owned(), "source()".to_owned());
+
+    let mut core = Core::new().unwrap();
+    let context = Context {
+        filters: Box::leak(Box::new(filters)),
+        private_filters: None,
+        aliases: Box::leak(Box::new(aliases)),
+        allow_builtin_filters: false,
+        remote: core.remote(),
+        request_headers: Rc::new(hyper::Headers::new()),
+        log_filters_header: Box::leak(Box::new(None)),
+        steps: Default::default(),
+        filter_log: Default::default(),
+        response_headers: Rc::new(RefCell::new(HashMap::new()))
+    };
+    let tree = apply(Filter { name: "source".to_owned(), args: vec![] }, "crop", vec![]);
+    assert!(filter::apply_aliases(&context, Filter { name: "source".to_owned(), args: vec![] }).is_err());
+
+    let response = core.run(respond(context, tree, None)).unwrap().unwrap();
+    let body = core.run(response.body().concat2()).unwrap();
+    assert_eq!(&*body, b"crop");
+}
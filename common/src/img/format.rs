@@ -28,6 +28,13 @@ impl ImageFormat {
             ImageFormat::Undefined => ""
         }
     }
+
+    pub fn supports_alpha(&self) -> bool {
+        match *self {
+            ImageFormat::PNG | ImageFormat::TIFF | ImageFormat::PDF | ImageFormat::SVG => true,
+            ImageFormat::JPEG | ImageFormat::PS | ImageFormat::Undefined => false
+        }
+    }
 }
 
 impl FromStr for ImageFormat {
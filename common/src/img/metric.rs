@@ -0,0 +1,86 @@
+use magick_rust;
+use std::str::FromStr;
+
+#[derive(PartialEq,Eq,Debug,Fail)]
+#[fail(display = "Unknown metric: {}", _0)]
+pub struct UnknownMetric(String);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Metric {
+    Undefined,
+    AE,
+    Fuzz,
+    MAE,
+    MEPP,
+    MSE,
+    NCC,
+    PAE,
+    PSNR,
+    PHash,
+    RMSE,
+    SSIM,
+    DSSIM,
+}
+
+impl FromStr for Metric {
+    type Err = UnknownMetric;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let lowercase = input.to_owned().to_lowercase();
+        Ok(match lowercase.as_str() {
+            "undefined" => Metric::Undefined,
+            "ae" => Metric::AE,
+            "fuzz" => Metric::Fuzz,
+            "mae" => Metric::MAE,
+            "mepp" => Metric::MEPP,
+            "mse" => Metric::MSE,
+            "ncc" => Metric::NCC,
+            "pae" => Metric::PAE,
+            "psnr" => Metric::PSNR,
+            "phash" => Metric::PHash,
+            "rmse" => Metric::RMSE,
+            "ssim" => Metric::SSIM,
+            "dssim" => Metric::DSSIM,
+            _ => return Err(UnknownMetric(input.to_owned()))
+        })
+    }
+}
+
+impl From<Metric> for magick_rust::bindings::MetricType {
+    fn from(from: Metric) -> magick_rust::bindings::MetricType {
+        match from {
+            Metric::Undefined => magick_rust::bindings::MetricType::UndefinedErrorMetric,
+            Metric::AE => magick_rust::bindings::MetricType::AbsoluteErrorMetric,
+            Metric::Fuzz => magick_rust::bindings::MetricType::FuzzErrorMetric,
+            Metric::MAE => magick_rust::bindings::MetricType::MeanAbsoluteErrorMetric,
+            Metric::MEPP => magick_rust::bindings::MetricType::MeanErrorPerPixelErrorMetric,
+            Metric::MSE => magick_rust::bindings::MetricType::MeanSquaredErrorMetric,
+            Metric::NCC => magick_rust::bindings::MetricType::NormalizedCrossCorrelationErrorMetric,
+            Metric::PAE => magick_rust::bindings::MetricType::PeakAbsoluteErrorMetric,
+            Metric::PSNR => magick_rust::bindings::MetricType::PeakSignalToNoiseRatioErrorMetric,
+            Metric::PHash => magick_rust::bindings::MetricType::PerceptualHashErrorMetric,
+            Metric::RMSE => magick_rust::bindings::MetricType::RootMeanSquaredErrorMetric,
+            Metric::SSIM => magick_rust::bindings::MetricType::StructuralSimilarityErrorMetric,
+            Metric::DSSIM => magick_rust::bindings::MetricType::StructuralDissimilarityErrorMetric,
+        }
+    }
+}
+
+impl From<magick_rust::bindings::MetricType> for Metric {
+    fn from(from: magick_rust::bindings::MetricType) -> Self {
+        match from {
+            magick_rust::bindings::MetricType::UndefinedErrorMetric => Metric::Undefined,
+            magick_rust::bindings::MetricType::AbsoluteErrorMetric => Metric::AE,
+            magick_rust::bindings::MetricType::FuzzErrorMetric => Metric::Fuzz,
+            magick_rust::bindings::MetricType::MeanAbsoluteErrorMetric => Metric::MAE,
+            magick_rust::bindings::MetricType::MeanErrorPerPixelErrorMetric => Metric::MEPP,
+            magick_rust::bindings::MetricType::MeanSquaredErrorMetric => Metric::MSE,
+            magick_rust::bindings::MetricType::NormalizedCrossCorrelationErrorMetric => Metric::NCC,
+            magick_rust::bindings::MetricType::PeakAbsoluteErrorMetric => Metric::PAE,
+            magick_rust::bindings::MetricType::PeakSignalToNoiseRatioErrorMetric => Metric::PSNR,
+            magick_rust::bindings::MetricType::PerceptualHashErrorMetric => Metric::PHash,
+            magick_rust::bindings::MetricType::RootMeanSquaredErrorMetric => Metric::RMSE,
+            magick_rust::bindings::MetricType::StructuralSimilarityErrorMetric => Metric::SSIM,
+            magick_rust::bindings::MetricType::StructuralDissimilarityErrorMetric => Metric::DSSIM,
+        }
+    }
+}
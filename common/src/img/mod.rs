@@ -1,5 +1,5 @@
 use failure::Error;
-use magick_rust::{MagickWand, PixelWand, magick_wand_genesis};
+use magick_rust::{self, MagickWand, PixelWand, magick_wand_genesis};
 use std::sync::{Once, ONCE_INIT};
 
 mod composite_op;
@@ -11,6 +11,9 @@ mod resolution_unit;
 mod format;
 mod alpha_channel;
 mod gravity;
+mod noise;
+mod metric;
+mod resource;
 pub use self::composite_op::CompositeOperator;
 pub use self::colorspace::Colorspace;
 pub use self::color_profile::ColorProfile;
@@ -20,6 +23,10 @@ pub use self::resolution_unit::ResolutionUnit;
 pub use self::format::ImageFormat;
 pub use self::alpha_channel::AlphaChannel;
 pub use self::gravity::Gravity;
+pub use self::noise::NoiseType;
+pub use self::metric::Metric;
+pub use self::resource::{ResourceType, ResourcesExhausted};
+use self::resource::magick_error;
 
 static START: Once = ONCE_INIT;
 
@@ -42,50 +49,159 @@ impl Image {
         }
         if let Some(source) = source.into() {
             instance.wand.read_image_blob(source).map_err(|msg|
-                format_err!("{}", msg)
+                magick_error(&instance.wand, msg)
             )?;
         }
         Ok(instance)
     }
 
+    /// Creates a new image filled with a single color.
+    pub fn solid(width: usize, height: usize, color: &str) -> Result<Self, Error> {
+        let instance = Image::new(None, None)?;
+        let mut pw = PixelWand::new();
+        pw.set_color(color).map_err(|msg| format_err!("{}", msg))?;
+        instance.wand.new_image(width, height, &pw).map_err(|msg| magick_error(&instance.wand, msg))?;
+        Ok(instance)
+    }
+
+    /// Creates an image using one of ImageMagick's built-in pseudo-image formats,
+    /// like `gradient:red-blue`. `options` are set before the image is read,
+    /// so they can be used to pass defines like `gradient:angle`.
+    pub fn pseudo(width: usize, height: usize, spec: &str, options: &[(&str, &str)]) -> Result<Self, Error> {
+        let instance = Image::new(None, None)?;
+        instance.wand.set_size(width, height).map_err(|msg| magick_error(&instance.wand, msg))?;
+        for (key, value) in options {
+            instance.wand.set_option(key, value).map_err(|msg| magick_error(&instance.wand, msg))?;
+        }
+        instance.wand.read_image(spec).map_err(|msg| magick_error(&instance.wand, msg))?;
+        Ok(instance)
+    }
+
     pub fn ping(&mut self, source: impl AsRef<[u8]>) -> Result<(), Error> {
-        self.wand.ping_image_blob(source).map_err(|msg| format_err!("{}", msg))
+        self.wand.ping_image_blob(source).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn read(&mut self, source: &Vec<u8>) -> Result<(), Error> {
-        self.wand.read_image_blob(source).map_err(|msg| format_err!("{}", msg))
+        self.wand.read_image_blob(source).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn encode(&self, format: ImageFormat) -> Result<Vec<u8>, Error> {
         match self.wand.write_image_blob(format.magick_str()) {
             Ok(val) => Ok(val),
-            Err(msg) => bail!("{}", msg)
+            Err(msg) => Err(magick_error(&self.wand, msg))
         }
     }
 
-    pub fn resize(&self, w: usize, h: usize, filter: &Filter) {
-        self.wand.resize_image(w, h, filter.into())
+    pub fn resize(&self, w: usize, h: usize, filter: &Filter) -> Result<(), Error> {
+        let result = unsafe {
+            magick_rust::bindings::MagickResizeImage(self.wand.wand, w, h, filter.into())
+        };
+        if result == magick_rust::bindings::MagickBooleanType::MagickTrue {
+            Ok(())
+        } else {
+            Err(magick_error(&self.wand, "Cannot resize the image."))
+        }
     }
 
-    pub fn fit_in(&self, w: usize, h: usize) {
-        self.wand.fit(w, h)
+    /// Scales every frame to fit within `w` x `h`, keeping the aspect ratio.
+    pub fn fit_in(&self, w: usize, h: usize) -> Result<(), Error> {
+        let width_ratio = w as f64 / self.width() as f64;
+        let height_ratio = h as f64 / self.height() as f64;
+        let (w, h) = if width_ratio < height_ratio {
+            (w, (self.height() as f64 * width_ratio) as usize)
+        } else {
+            ((self.width() as f64 * height_ratio) as usize, h)
+        };
+        unsafe {
+            magick_rust::bindings::MagickResetIterator(self.wand.wand);
+            while magick_rust::bindings::MagickNextImage(self.wand.wand) == magick_rust::bindings::MagickBooleanType::MagickTrue {
+                self.resize(w, h, &Filter::Lanczos)?;
+            }
+        }
+        Ok(())
     }
 
     pub fn compose(&self, operator: &CompositeOperator, other: &Image, x: isize, y: isize) -> Result<(), Error> {
         self.wand.compose_images(&other.wand, (*operator).into(), true, x, y)
-            .map_err(|msg| format_err!("{}", msg))
+            .map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    /// Returns the distortion between this image and `reference`, which must have the same size.
+    pub fn compare(&self, reference: &Image, metric: &Metric) -> f64 {
+        self.wand.compare_images(&reference.wand, metric.to_owned().into()).0
     }
 
     pub fn crop(&self, x: isize, y: isize, width: usize, height: usize) -> Result<(), Error> {
-        self.wand.crop_image(width, height, x, y).map_err(|msg| format_err!("{}", msg))
+        self.wand.crop_image(width, height, x, y).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn extend(&self, x: isize, y: isize, width: usize, height: usize) -> Result<(), Error> {
-        self.wand.extend_image(width, height, x, y).map_err(|msg| format_err!("{}", msg))
+        self.wand.extend_image(width, height, x, y).map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    /// Forgets the virtual canvas left behind by operations like `crop`.
+    pub fn reset_page(&mut self) -> Result<(), Error> {
+        self.wand.reset_image_page("0x0+0+0").map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn trim(&self, fuzz: f64) -> Result<(), Error> {
-        self.wand.trim_image(fuzz).map_err(|msg| format_err!("{}", msg))
+        self.wand.trim_image(fuzz).map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    /// Returns the virtual canvas of the image as (width, height, x offset, y offset).
+    pub fn page(&self) -> Result<(usize, usize, isize, isize), Error> {
+        self.wand.get_image_page().map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.wand.get_image_alpha_channel()
+    }
+
+    /// Number of images (pages, frames or layers) read from the source.
+    pub fn pages(&self) -> usize {
+        self.wand.get_number_images()
+    }
+
+    pub fn property(&self, name: &str) -> Option<String> {
+        self.wand.get_image_property(name).ok()
+    }
+
+    /// Returns 8-bit pixel values of the whole image, with channels ordered as in `map` (e.g. "RGBA").
+    pub fn pixels(&self, map: &str) -> Result<Vec<u8>, Error> {
+        self.wand.export_image_pixels(0, 0, self.width(), self.height(), map)
+            .ok_or_else(|| format_err!("Cannot export {} pixels of the image.", map))
+    }
+
+    /// Like `pixels`, but with 16-bit values, so that deep images keep their precision.
+    pub fn pixels16(&self, map: &str) -> Result<Vec<u16>, Error> {
+        let (width, height) = (self.width(), self.height());
+        let mut pixels = vec![0u16; width * height * map.len()];
+        let c_map = ::std::ffi::CString::new(map)?;
+        let result = unsafe {
+            magick_rust::bindings::MagickExportImagePixels(
+                self.wand.wand, 0, 0, width, height, c_map.as_ptr(),
+                magick_rust::bindings::StorageType::ShortPixel,
+                pixels.as_mut_ptr() as *mut ::std::os::raw::c_void
+            )
+        };
+        if result == magick_rust::bindings::MagickBooleanType::MagickTrue {
+            Ok(pixels)
+        } else {
+            Err(magick_error(&self.wand, &format!("Cannot export {} pixels of the image.", map)))
+        }
+    }
+
+    /// Scales the image by picking pixels rather than averaging them, so that every pixel
+    /// of the result has a value which occurs in the original image.
+    pub fn sample(&mut self, w: usize, h: usize) -> Result<(), Error> {
+        let result = unsafe {
+            magick_rust::bindings::MagickSampleImage(self.wand.wand, w, h)
+        };
+        if result == magick_rust::bindings::MagickBooleanType::MagickTrue {
+            Ok(())
+        } else {
+            Err(magick_error(&self.wand, "Cannot sample the image."))
+        }
     }
 
     pub fn width(&self) -> usize {
@@ -97,26 +213,26 @@ impl Image {
     }
 
     pub fn set_quality(&mut self, quality: usize) -> Result<(), Error> {
-        self.wand.set_image_compression_quality(quality).map_err(|msg| format_err!("{}", msg))
+        self.wand.set_image_compression_quality(quality).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn format(&self) -> Result<ImageFormat, Error> {
-        Ok(self.wand.get_image_format().map_err(|msg| format_err!("{}", msg))?.parse()?)
+        Ok(self.wand.get_image_format().map_err(|msg| magick_error(&self.wand, msg))?.parse()?)
     }
 
     pub fn set_format(&mut self, format: &ImageFormat) -> Result<(), Error> {
-        let result = self.wand.set_image_format(format.magick_str()).map_err(|msg| format_err!("{}", msg));
+        let result = self.wand.set_image_format(format.magick_str()).map_err(|msg| magick_error(&self.wand, msg));
         if format == &ImageFormat::TIFF {
             // If we don't set that, some popular photo editing programs
             // might have problems with opening the file.
-            self.wand.set_option("tiff:rows-per-strip", "2").map_err(|msg| format_err!("{}", msg))?;
+            self.wand.set_option("tiff:rows-per-strip", "2").map_err(|msg| magick_error(&self.wand, msg))?;
         } else if format == &ImageFormat::PDF {
             // For some reason, page geometry can assume incorrect values.
             // Resetting it seems to help.
-            self.wand.reset_image_page("0x0").map_err(|msg| format_err!("{}", msg))?;
+            self.wand.reset_image_page("0x0").map_err(|msg| magick_error(&self.wand, msg))?;
             // Also, for some reason ImageMagick needs the wand resolution set for PDFs.
             let (x_dpi, y_dpi) = self.resolution()?;
-            self.wand.set_resolution(x_dpi, y_dpi).map_err(|msg| format_err!("{}", msg))?;
+            self.wand.set_resolution(x_dpi, y_dpi).map_err(|msg| magick_error(&self.wand, msg))?;
         }
         result
     }
@@ -126,7 +242,7 @@ impl Image {
     }
 
     pub fn set_colorspace(&mut self, colorspace: &Colorspace) -> Result<(), Error> {
-        self.wand.transform_image_colorspace(colorspace.to_owned().into()).map_err(|msg| format_err!("{}", msg))
+        self.wand.transform_image_colorspace(colorspace.to_owned().into()).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn gravity(&self) -> Gravity {
@@ -134,11 +250,11 @@ impl Image {
     }
 
     pub fn set_gravity(&mut self, gravity: &Gravity) -> Result<(), Error> {
-        self.wand.set_gravity(gravity.to_owned().into()).map_err(|msg| format_err!("{}", msg))
+        self.wand.set_gravity(gravity.to_owned().into()).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn set_alpha_channel(&mut self, alpha_channel: &AlphaChannel) -> Result<(), Error> {
-        self.wand.set_image_alpha_channel(alpha_channel.to_owned().into()).map_err(|msg| format_err!("{}", msg))
+        self.wand.set_image_alpha_channel(alpha_channel.to_owned().into()).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn compression(&self) -> CompressionType {
@@ -146,16 +262,16 @@ impl Image {
     }
 
     pub fn set_compression(&mut self, compression: &CompressionType) -> Result<(), Error> {
-        self.wand.set_compression(compression.to_owned().into()).map_err(|msg| format_err!("{}", msg))
+        self.wand.set_compression(compression.to_owned().into()).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn transform_color_profile(&mut self, src_profile: &ColorProfile, dest_profile: &ColorProfile) -> Result<(), Error> {
-        self.wand.profile_image("icc", src_profile).map_err(|msg| format_err!("{}", msg))?;
-        self.wand.profile_image("icc", dest_profile).map_err(|msg| format_err!("{}", msg))
+        self.wand.profile_image("icc", src_profile).map_err(|msg| magick_error(&self.wand, msg))?;
+        self.wand.profile_image("icc", dest_profile).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn set_color_profile(&mut self, profile: &ColorProfile) -> Result<(), Error> {
-        self.wand.profile_image("icc", profile).map_err(|msg| format_err!("{}", msg))
+        self.wand.profile_image("icc", profile).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn resample(&mut self, x_dpi: f64, y_dpi: f64, filter: &Filter) {
@@ -163,12 +279,12 @@ impl Image {
     }
 
     pub fn resolution(&self) -> Result<(f64, f64), Error> {
-        self.wand.get_image_resolution().map_err(|msg| format_err!("{}", msg))
+        self.wand.get_image_resolution().map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn set_resolution(&mut self, x_dpi: f64, y_dpi: f64) -> Result<(), Error> {
-        self.wand.profile_image("8bim", None).map_err(|msg| format_err!("{}", msg))?;
-        self.wand.set_image_resolution(x_dpi, y_dpi).map_err(|msg| format_err!("{}", msg))
+        self.wand.profile_image("8bim", None).map_err(|msg| magick_error(&self.wand, msg))?;
+        self.wand.set_image_resolution(x_dpi, y_dpi).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn resolution_unit(&self) -> ResolutionUnit {
@@ -176,28 +292,130 @@ impl Image {
     }
 
     pub fn set_resolution_unit(&mut self, unit: &ResolutionUnit) -> Result<(), Error> {
-        self.wand.set_image_units(unit.to_owned().into()).map_err(|msg| format_err!("{}", msg))
+        self.wand.set_image_units(unit.to_owned().into()).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn set_background_color(&self, color: &str) -> Result<(), Error> {
         let mut pw = PixelWand::new();
         pw.set_color(color).map_err(|msg| format_err!("{}", msg))?;
-        self.wand.set_image_background_color(&pw).map_err(|msg| format_err!("{}", msg))
+        self.wand.set_image_background_color(&pw).map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    pub fn blur(&mut self, sigma: f64) -> Result<(), Error> {
+        // A radius of 0 lets ImageMagick choose one suitable for the given sigma.
+        self.wand.blur_image(0.0, sigma).map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    pub fn add_noise(&mut self, noise: &NoiseType, attenuate: f64) -> Result<(), Error> {
+        self.wand.add_noise_image(noise.to_owned().into(), attenuate).map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    /// Reduces the number of unique colors in the image, without dithering.
+    pub fn quantize(&mut self, colors: usize) -> Result<(), Error> {
+        let colorspace = self.colorspace().into();
+        self.wand.quantize_image(colors, colorspace, 0, magick_rust::bindings::DitherMethod::NoDitherMethod, false)
+            .map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    /// Returns every unique color of the image (as an sRGB hex string) with its pixel count.
+    /// This is only reasonable for images with a limited number of colors, e.g. after `quantize`.
+    pub fn histogram(&self) -> Result<Vec<(String, usize)>, Error> {
+        let colors = self.wand.get_image_histogram().ok_or_else(|| format_err!("Cannot compute the image histogram."))?;
+        Ok(colors.iter().map(|color| {
+            let hex = format!("#{:02x}{:02x}{:02x}",
+                (color.get_red() * 255.0).round() as u8,
+                (color.get_green() * 255.0).round() as u8,
+                (color.get_blue() * 255.0).round() as u8
+            );
+            (hex, color.get_color_count())
+        }).collect())
+    }
+
+    /// Rotates the image clockwise, filling the uncovered corners with `background`.
+    pub fn rotate(&mut self, degrees: f64, background: &str) -> Result<(), Error> {
+        let mut pw = PixelWand::new();
+        pw.set_color(background).map_err(|msg| format_err!("{}", msg))?;
+        self.wand.rotate_image(&pw, degrees).map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn flip(&mut self) -> Result<(), Error> {
-        self.wand.flip_image().map_err(|msg| format_err!("{}", msg))
+        self.wand.flip_image().map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn flop(&mut self) -> Result<(), Error> {
-        self.wand.flop_image().map_err(|msg| format_err!("{}", msg))
+        self.wand.flop_image().map_err(|msg| magick_error(&self.wand, msg))
     }
 
     pub fn sepia(&mut self, threshold: f64) -> Result<(), Error> {
-        self.wand.sepia_tone_image(threshold).map_err(|msg| format_err!("{}", msg))
+        self.wand.sepia_tone_image(threshold).map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    pub fn brightness_contrast(&mut self, brightness: f64, contrast: f64) -> Result<(), Error> {
+        self.wand.brightness_contrast_image(brightness, contrast).map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    pub fn modulate(&mut self, brightness: f64, saturation: f64, hue: f64) -> Result<(), Error> {
+        self.wand.modulate_image(brightness, saturation, hue).map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    pub fn gamma(&mut self, gamma: f64) -> Result<(), Error> {
+        self.wand.gamma_image(gamma).map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    /// `black_point` and `white_point` are fractions of the quantum range (0.0 - 1.0).
+    pub fn levels(&mut self, black_point: f64, white_point: f64, gamma: f64) -> Result<(), Error> {
+        let range = quantum_range();
+        self.wand.level_image(black_point * range, gamma, white_point * range).map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    pub fn auto_level(&mut self) -> Result<(), Error> {
+        self.wand.auto_level_image().map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    pub fn normalize(&mut self) -> Result<(), Error> {
+        self.wand.normalize_image().map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    pub fn negate(&mut self) -> Result<(), Error> {
+        self.wand.negate_image().map_err(|msg| magick_error(&self.wand, msg))
+    }
+
+    /// `threshold` is a fraction of the quantum range (0.0 - 1.0).
+    pub fn threshold(&mut self, threshold: f64) -> Result<(), Error> {
+        self.wand.threshold_image(threshold * quantum_range()).map_err(|msg| magick_error(&self.wand, msg))
     }
 }
 
+/// The maximum value of a single channel, which depends on how ImageMagick was built.
+pub fn quantum_range() -> f64 {
+    init_magick();
+    let mut range = 0;
+    unsafe {
+        magick_rust::bindings::MagickGetQuantumRange(&mut range);
+    }
+    range as f64
+}
+
+/// Sets a process-wide ImageMagick resource limit. Sizes are in bytes, area in pixels
+/// and time in seconds.
+pub fn set_resource_limit(resource: ResourceType, limit: u64) -> Result<(), Error> {
+    init_magick();
+    let result = unsafe {
+        magick_rust::bindings::MagickSetResourceLimit(resource.into(), limit)
+    };
+    if result == magick_rust::bindings::MagickBooleanType::MagickTrue {
+        Ok(())
+    } else {
+        bail!("Couldn't set the {:?} resource limit to {}.", resource, limit)
+    }
+}
+
+pub fn resource_limit(resource: ResourceType) -> u64 {
+    init_magick();
+    unsafe {
+        magick_rust::bindings::MagickGetResourceLimit(resource.into())
+    }
+}
 
 fn init_magick() {
     START.call_once(|| {
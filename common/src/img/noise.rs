@@ -0,0 +1,66 @@
+use magick_rust;
+use std::str::FromStr;
+
+#[derive(PartialEq,Eq,Debug,Fail)]
+#[fail(display = "Unknown noise type: {}", _0)]
+pub struct UnknownNoiseType(String);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NoiseType {
+    Undefined,
+    Uniform,
+    Gaussian,
+    MultiplicativeGaussian,
+    Impulse,
+    Laplacian,
+    Poisson,
+    Random,
+}
+
+impl FromStr for NoiseType {
+    type Err = UnknownNoiseType;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let lowercase = input.to_owned().to_lowercase();
+        Ok(match lowercase.as_str() {
+            "undefined" => NoiseType::Undefined,
+            "uniform" => NoiseType::Uniform,
+            "gaussian" => NoiseType::Gaussian,
+            "multiplicative_gaussian" => NoiseType::MultiplicativeGaussian,
+            "impulse" => NoiseType::Impulse,
+            "laplacian" => NoiseType::Laplacian,
+            "poisson" => NoiseType::Poisson,
+            "random" => NoiseType::Random,
+            _ => return Err(UnknownNoiseType(input.to_owned()))
+        })
+    }
+}
+
+impl From<NoiseType> for magick_rust::bindings::NoiseType {
+    fn from(from: NoiseType) -> magick_rust::bindings::NoiseType {
+        match from {
+            NoiseType::Undefined => magick_rust::bindings::NoiseType::UndefinedNoise,
+            NoiseType::Uniform => magick_rust::bindings::NoiseType::UniformNoise,
+            NoiseType::Gaussian => magick_rust::bindings::NoiseType::GaussianNoise,
+            NoiseType::MultiplicativeGaussian => magick_rust::bindings::NoiseType::MultiplicativeGaussianNoise,
+            NoiseType::Impulse => magick_rust::bindings::NoiseType::ImpulseNoise,
+            NoiseType::Laplacian => magick_rust::bindings::NoiseType::LaplacianNoise,
+            NoiseType::Poisson => magick_rust::bindings::NoiseType::PoissonNoise,
+            NoiseType::Random => magick_rust::bindings::NoiseType::RandomNoise,
+        }
+    }
+}
+
+impl From<magick_rust::bindings::NoiseType> for NoiseType {
+    fn from(from: magick_rust::bindings::NoiseType) -> Self {
+        match from {
+            magick_rust::bindings::NoiseType::UndefinedNoise => NoiseType::Undefined,
+            magick_rust::bindings::NoiseType::UniformNoise => NoiseType::Uniform,
+            magick_rust::bindings::NoiseType::GaussianNoise => NoiseType::Gaussian,
+            magick_rust::bindings::NoiseType::MultiplicativeGaussianNoise => NoiseType::MultiplicativeGaussian,
+            magick_rust::bindings::NoiseType::ImpulseNoise => NoiseType::Impulse,
+            magick_rust::bindings::NoiseType::LaplacianNoise => NoiseType::Laplacian,
+            magick_rust::bindings::NoiseType::PoissonNoise => NoiseType::Poisson,
+            magick_rust::bindings::NoiseType::RandomNoise => NoiseType::Random,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use magick_rust;
+use failure::Error;
+use std::str::FromStr;
+
+#[derive(PartialEq,Eq,Debug,Fail)]
+#[fail(display = "Unknown resource type: {}", _0)]
+pub struct UnknownResourceType(String);
+
+/// ImageMagick resources which can be limited.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResourceType {
+    Area,
+    Disk,
+    File,
+    Height,
+    ListLength,
+    Map,
+    Memory,
+    Thread,
+    Throttle,
+    Time,
+    Width,
+}
+
+impl FromStr for ResourceType {
+    type Err = UnknownResourceType;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let lowercase = input.to_owned().to_lowercase();
+        Ok(match lowercase.as_str() {
+            "area" => ResourceType::Area,
+            "disk" => ResourceType::Disk,
+            "file" => ResourceType::File,
+            "height" => ResourceType::Height,
+            "list-length" => ResourceType::ListLength,
+            "map" => ResourceType::Map,
+            "memory" => ResourceType::Memory,
+            "thread" => ResourceType::Thread,
+            "throttle" => ResourceType::Throttle,
+            "time" => ResourceType::Time,
+            "width" => ResourceType::Width,
+            _ => return Err(UnknownResourceType(input.to_owned()))
+        })
+    }
+}
+
+impl From<ResourceType> for magick_rust::bindings::ResourceType {
+    fn from(from: ResourceType) -> magick_rust::bindings::ResourceType {
+        match from {
+            ResourceType::Area => magick_rust::bindings::ResourceType::AreaResource,
+            ResourceType::Disk => magick_rust::bindings::ResourceType::DiskResource,
+            ResourceType::File => magick_rust::bindings::ResourceType::FileResource,
+            ResourceType::Height => magick_rust::bindings::ResourceType::HeightResource,
+            ResourceType::ListLength => magick_rust::bindings::ResourceType::ListLengthResource,
+            ResourceType::Map => magick_rust::bindings::ResourceType::MapResource,
+            ResourceType::Memory => magick_rust::bindings::ResourceType::MemoryResource,
+            ResourceType::Thread => magick_rust::bindings::ResourceType::ThreadResource,
+            ResourceType::Throttle => magick_rust::bindings::ResourceType::ThrottleResource,
+            ResourceType::Time => magick_rust::bindings::ResourceType::TimeResource,
+            ResourceType::Width => magick_rust::bindings::ResourceType::WidthResource,
+        }
+    }
+}
+
+/// ImageMagick ran out of resources, e.g. because too many requests are processed at once.
+/// Sources which are too large on their own are rejected before they're decoded.
+#[derive(Debug, Fail)]
+#[fail(display = "{}", _0)]
+pub struct ResourcesExhausted(pub String);
+
+/// Turns an error from ImageMagick into `ResourcesExhausted` if the wand reports
+/// a resource limit exception.
+pub fn magick_error(wand: &magick_rust::MagickWand, message: &str) -> Error {
+    use magick_rust::bindings::ExceptionType;
+    let exception = unsafe { magick_rust::bindings::MagickGetExceptionType(wand.wand) };
+    match exception {
+        ExceptionType::ResourceLimitError | ExceptionType::ResourceLimitFatalError => Error::from(ResourcesExhausted(message.to_owned())),
+        _ => format_err!("{}", message)
+    }
+}
@@ -16,11 +16,17 @@ pub mod url;
 pub mod filter;
 pub mod cfg;
 pub mod prelude;
+pub mod frontend;
+pub mod timeout;
+/// Middlewares can handle a request on their own (e.g. to implement a different url syntax),
+/// using the context to execute filters.
+pub type Middleware = Fn(Rc<hyper::Request>, filter::Context) -> Box<futures::future::Future<Item=Option<hyper::Response>, Error=failure::Error>>;
+
 pub struct PluginInformation {
     pub filters: filter::FilterMap,
     pub init: Option<&'static Fn() -> Result<(), failure::Error>>,
     pub exit: Option<&'static Fn() -> Result<(), failure::Error>>,
-    pub middleware: Option<&'static Fn(Rc<hyper::Request>) -> Box<futures::future::Future<Item=Option<hyper::Response>, Error=failure::Error>>>
+    pub middleware: Option<&'static Middleware>
 }
 
 impl PluginInformation {
@@ -43,7 +49,7 @@ impl PluginInformation {
         self
     }
 
-    pub fn with_middleware(mut self, middleware: &'static Fn(Rc<hyper::Request>) -> Box<futures::future::Future<Item=Option<hyper::Response>, Error=failure::Error>>) -> Self {
+    pub fn with_middleware(mut self, middleware: &'static Middleware) -> Self {
         self.middleware = Some(middleware);
         self
     }
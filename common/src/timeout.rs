@@ -0,0 +1,79 @@
+//! Deadlines for futures, reported to the client as 504 Gateway Timeout.
+//!
+//! A deadline can only fire when the reactor gets control back, i.e. between steps:
+//! image operations run on the reactor thread and can't be interrupted midway.
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::time::Duration;
+use hyper;
+use futures::{future, Future as FutureTrait};
+use futures::future::Either;
+use tokio_core::reactor::{Handle, Timeout};
+use failure::Error;
+use crate::filter::error_response;
+
+/// An error which is returned to the client as 504 Gateway Timeout, naming the step which timed out.
+pub fn timed_out(step: String) -> Error {
+    error_response(hyper::StatusCode::GatewayTimeout, format!("Timed out {}.", step))
+}
+
+#[derive(Default)]
+struct Running {
+    next_id: usize,
+    steps: Vec<(usize, String)>,
+}
+
+/// Filters which haven't finished yet, in the order they were started,
+/// so that a deadline can tell which one it interrupted.
+#[derive(Clone, Default)]
+pub struct Steps(Rc<RefCell<Running>>);
+
+impl Steps {
+    pub fn start(&self, step: String) -> usize {
+        let mut running = self.0.borrow_mut();
+        let id = running.next_id;
+        running.next_id += 1;
+        running.steps.push((id, step));
+        id
+    }
+
+    pub fn finish(&self, id: usize) {
+        self.0.borrow_mut().steps.retain(|&(step_id, _)| step_id != id);
+    }
+
+    /// The most recently started step which is still running. Filters start their
+    /// inputs after themselves, so this is the innermost one holding things up.
+    pub fn current(&self) -> Option<String> {
+        self.0.borrow().steps.last().map(|&(_, ref step)| step.clone())
+    }
+}
+
+/// Fails with a 504 if `future` doesn't resolve within `duration`.
+/// `step` describes what was being done when the deadline passed,
+/// e.g. "downloading http://example.com/img.jpg".
+pub fn with_timeout<F, S>(future: F, duration: Duration, handle: &Handle, step: S) -> Box<FutureTrait<Item = F::Item, Error = Error>>
+    where F: FutureTrait<Error = Error> + 'static, F::Item: 'static, S: FnOnce() -> String + 'static
+{
+    let timer = match Timeout::new(duration, handle) {
+        Ok(timer) => timer,
+        Err(e) => return Box::new(future::err(Error::from(e)))
+    };
+    Box::new(future.select2(timer).then(move |result| match result {
+        Ok(Either::A((item, _))) => Ok(item),
+        Ok(Either::B(_)) => Err(timed_out(step())),
+        Err(Either::A((e, _))) => Err(e),
+        Err(Either::B((e, _))) => Err(Error::from(e)),
+    }))
+}
+
+#[test]
+fn test_steps() {
+    let steps = Steps::default();
+    let resize = steps.start("resize".to_owned());
+    let download = steps.start("download".to_owned());
+    assert_eq!(steps.current(), Some("download".to_owned()));
+    steps.finish(download);
+    assert_eq!(steps.current(), Some("resize".to_owned()));
+    steps.finish(resize);
+    assert_eq!(steps.current(), None);
+}
@@ -71,7 +71,7 @@ named!(float(&str) -> f32, map!(
 // }
 
 named!(unit(&str) -> SizeUnit, map_res!(
-    alt!(tag!("px") | tag!("hcm") | tag!("vcm") | tag!("hin") | tag!("vin") | tag!("w") | tag!("h") | tag!("")),
+    alt!(tag!("px") | tag!("hcm") | tag!("vcm") | tag!("hin") | tag!("vin") | tag!("dpr") | tag!("w") | tag!("h") | tag!("")),
     FromStr::from_str
 ));
 
@@ -114,6 +114,15 @@ pub fn parse(input: &str) -> Result<Filter, Error> {
     }
 }
 
+/// Parses a single filter argument, as it would appear inside a filter's parentheses.
+pub fn parse_arg(input: &str) -> Result<FilterArg, Error> {
+    let terminated = format!("{})", input);
+    match filter_arg(&terminated) {
+        Ok((")", arg)) => Ok(arg),
+        _ => bail!("Invalid argument: {}", input),
+    }
+}
+
 #[test]
 fn test_simple_filter() {
     assert_eq!(filter("download(s3:2666/img.jpg)"), IResult::Done("", Filter {
@@ -133,3 +142,13 @@ fn test_nested_filter() {
         ]
     }))
 }
+
+#[test]
+fn test_float_display_roundtrip() {
+    for &value in &[2.0, 0.0000001, 12345678.0] {
+        match parse_arg(&format!("{}", FilterArg::Float(value, SizeUnit::Width))).unwrap() {
+            FilterArg::Float(parsed, SizeUnit::Width) => assert_eq!(parsed, value),
+            arg => panic!("Expected a float, got {:?}", arg)
+        }
+    }
+}
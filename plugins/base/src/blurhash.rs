@@ -0,0 +1,89 @@
+//! Encoder for [BlurHash](https://blurha.sh) placeholders.
+use std::f64::consts::PI;
+
+const BASE83: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+fn base83(value: u32, length: u32, output: &mut String) {
+    for i in 1..=length {
+        let digit = (value / 83u32.pow(length - i)) % 83;
+        output.push(BASE83[digit as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let v = value as f64 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u32 {
+    let v = value.max(0.0).min(1.0);
+    if v <= 0.0031308 {
+        (v * 12.92 * 255.0 + 0.5) as u32
+    } else {
+        ((1.055 * v.powf(1.0 / 2.4) - 0.055) * 255.0 + 0.5) as u32
+    }
+}
+
+fn sign_pow(value: f64, exp: f64) -> f64 {
+    value.abs().powf(exp).copysign(value)
+}
+
+/// Encodes `rgb` (3 bytes per pixel, row by row) using `components_x` by `components_y`
+/// components. Both component counts must be between 1 and 9.
+pub fn encode(components_x: u32, components_y: u32, width: usize, height: usize, rgb: &[u8]) -> String {
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for j in 0..components_y {
+        for i in 0..components_x {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0; 3];
+            for y in 0..height {
+                for x in 0..width {
+                    let basis = (PI * i as f64 * x as f64 / width as f64).cos()
+                        * (PI * j as f64 * y as f64 / height as f64).cos();
+                    let pixel = &rgb[3 * (y * width + x)..];
+                    for c in 0..3 {
+                        factor[c] += basis * srgb_to_linear(pixel[c]);
+                    }
+                }
+            }
+            let scale = normalisation / (width * height) as f64;
+            factors.push([factor[0] * scale, factor[1] * scale, factor[2] * scale]);
+        }
+    }
+
+    let mut hash = String::new();
+    base83((components_x - 1) + (components_y - 1) * 9, 1, &mut hash);
+
+    let (dc, ac) = factors.split_first().unwrap();
+    let max_value = if ac.len() > 0 {
+        let actual_max = ac.iter().flat_map(|f| f.iter()).fold(0.0f64, |max, v| max.max(v.abs()));
+        let quantised_max = (actual_max * 166.0 - 0.5).floor().min(82.0).max(0.0) as u32;
+        base83(quantised_max, 1, &mut hash);
+        (quantised_max + 1) as f64 / 166.0
+    } else {
+        base83(0, 1, &mut hash);
+        1.0
+    };
+
+    base83((linear_to_srgb(dc[0]) << 16) + (linear_to_srgb(dc[1]) << 8) + linear_to_srgb(dc[2]), 4, &mut hash);
+    for factor in ac {
+        let quant = |v: f64| (sign_pow(v / max_value, 0.5) * 9.0 + 9.5).floor().min(18.0).max(0.0) as u32;
+        base83(quant(factor[0]) * 19 * 19 + quant(factor[1]) * 19 + quant(factor[2]), 2, &mut hash);
+    }
+    hash
+}
+
+#[test]
+fn test_solid_white() {
+    assert_eq!(encode(1, 1, 2, 2, &[255; 12]), "00TSUA");
+}
+
+#[test]
+fn test_hash_length() {
+    let rgb: Vec<u8> = (0..8 * 6 * 3).map(|i| (i * 7 % 256) as u8).collect();
+    assert_eq!(encode(4, 3, 8, 6, &rgb).len(), 4 + 2 + 2 * 11);
+}
@@ -15,6 +15,8 @@ use std::io::Write;
 use serde_json;
 
 use lru_cache::LruCache;
+use limits;
+use client_hints;
 
 #[derive(Debug, Clone, Eq, PartialEq, Fail)]
 #[fail(display="No such cache: {}", _0)]
@@ -44,7 +46,13 @@ pub fn cache(name: &str) -> Result<MutexGuard<'static, LruCache>, Error> {
 #[derive(Serialize, Deserialize, Debug)]
 struct CacheMetadata {
     content_type: String,
-    dpi: Option<(f64, f64)>
+    dpi: Option<(f64, f64)>,
+    /// Response headers set by the cached filters (e.g. `X-Trim-Geometry`), replayed on a hit.
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// Log entries of the cached filters, replayed on a hit.
+    #[serde(default)]
+    log: Vec<String>
 }
 
 #[derive(Debug)]
@@ -67,7 +75,9 @@ impl FilterResult for CacheEntry {
     }
 
     fn image(self: Box<Self>) -> Result<Image, Error> {
-        Image::new(&*self.buffer, self.metadata.dpi.map(|dpi| dpi.0)).into()
+        let resolution = self.metadata.dpi.map(|dpi| dpi.0);
+        limits::check_source(&*self.buffer, resolution)?;
+        Image::new(&*self.buffer, resolution)
     }
 }
 
@@ -86,10 +96,12 @@ fn cache_path(url: &str) -> String {
     path
 }
 
-fn save(cache_name: &str, path: String, result: &Box<FilterResult>) -> Result<(), Error> {
+fn save(cache_name: &str, path: String, result: &Box<FilterResult>, headers: HashMap<String, String>, log: Vec<String>) -> Result<(), Error> {
     let metadata = CacheMetadata {
         content_type: format!("{}", result.content_type()?.0),
-        dpi: result.dpi().ok()
+        dpi: result.dpi().ok(),
+        headers,
+        log
     };
     let mut output: Vec<u8> = vec![];
     let meta = serde_json::to_string(&metadata)?;
@@ -119,27 +131,48 @@ fn get_cache_entry(cache_name: &str, params: &str) -> Result<CacheEntry, Error>
 
 fn filter_result(context: &mut Context, cache_name: String, args: &Args) -> Result<Box<Future>, Error> {
     if let Some(&FilterArg::Img(ref filter)) = args.get(0) {
-        let params = cache_path(&format!("{:?}", args[0]));
+        // Client hints are resolved first, so that they are part of the key,
+        // and their response headers are set whether the entry is cached or not.
+        let filter = client_hints::resolve_tree(context, filter);
+        let params = cache_path(&format!("{:?}", FilterArg::Img(filter.clone())));
 
         if let Ok(entry) = get_cache_entry(&cache_name, &params) {
-            context.log_filters_header.as_ref().map(|header_name|
-                context.response_headers.entry(header_name.clone()).and_modify(|value| {
-                    value.push_str("_hit(");
-                    value.push_str(cache_name.as_str());
-                    value.push_str(")");
-                })
-            );
+            context.log_details(&format!("_hit({})", cache_name));
+            // The log of the cached filters is replayed, so that e.g. redirects are still reported.
+            if context.log_filters_header.is_some() {
+                for line in &entry.metadata.log {
+                    context.filter_log.push(line.clone());
+                }
+            }
+            {
+                let mut response_headers = context.response_headers.borrow_mut();
+                for (name, value) in &entry.metadata.headers {
+                    if name.eq_ignore_ascii_case("vary") {
+                        client_hints::add_vary(&mut response_headers, value);
+                    } else {
+                        response_headers.insert(name.clone(), value.clone());
+                    }
+                }
+            }
             Ok(Box::new(future::ok(Box::new(entry).into())))
         } else {
-            context.log_filters_header.as_ref().map(|header_name|
-                context.response_headers.entry(header_name.clone()).and_modify(|value| {
-                    value.push_str("_miss(");
-                    value.push_str(cache_name.as_str());
-                    value.push_str(")");
-                })
-            );
-            Ok(Box::new(exec_filter(context, filter).map(move |img| {
-                save(&cache_name, params, &img).unwrap_or_else(|e| eprintln!("{}", e));
+            context.log_details(&format!("_miss({})", cache_name));
+            // Headers are only set once the filters are done, so anything that changed
+            // in the meantime belongs in the cache entry.
+            let response_headers = context.response_headers.clone();
+            let before = response_headers.borrow().clone();
+            // Filters log themselves as they start, which happens right away.
+            let filter_log = context.filter_log.clone();
+            let log_start = filter_log.len();
+            let future = exec_filter(context, &filter);
+            let log_end = filter_log.len();
+            Ok(Box::new(future.map(move |img| {
+                let headers = response_headers.borrow().iter()
+                    .filter(|&(name, value)| before.get(name) != Some(value))
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect();
+                let log = filter_log.entries(log_start..log_end);
+                save(&cache_name, params, &img, headers, log).unwrap_or_else(|e| eprintln!("{}", e));
                 img.into()
             })))
         }
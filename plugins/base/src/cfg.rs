@@ -1,5 +1,6 @@
 use std::collections::HashMap;
-use ::imaginator::img::ImageFormat;
+use ::imaginator::img::{ImageFormat, ResourceType};
+use serde::{Deserialize, Deserializer};
 use serde_humanize_rs;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -7,6 +8,17 @@ pub struct ImageConfig {
     pub max_width: Option<isize>,
     pub max_height: Option<isize>,
     pub supported_formats: Option<Vec<ImageFormat>>,
+    #[serde(flatten)]
+    pub source_limits: SourceLimits,
+    /// ImageMagick resource limits, set at startup. They are process-wide, so they have
+    /// to leave room for the biggest sources allowed by `source_profiles`.
+    #[serde(default)]
+    pub resource_limits: ResourceLimits,
+    /// Named source limits, used by the `limits` filter instead of the default ones,
+    /// e.g. to allow bigger sources in print presets. Only the source pixel and size limits
+    /// can be overridden per alias: `resource_limits` stay the same for every request.
+    #[serde(default)]
+    pub source_profiles: HashMap<String, SourceLimits>,
 }
 
 impl Default for ImageConfig {
@@ -14,11 +26,64 @@ impl Default for ImageConfig {
         ImageConfig {
             max_width: None,
             max_height: None,
-            supported_formats: None
+            supported_formats: None,
+            source_limits: SourceLimits::default(),
+            resource_limits: ResourceLimits::default(),
+            source_profiles: HashMap::new()
         }
     }
 }
 
+/// Limits checked before a source image is decoded.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SourceLimits {
+    /// Maximum number of pixels in a source image, summed over all its frames.
+    #[serde(default)]
+    pub max_source_pixels: Option<usize>,
+    /// Maximum size of a source image, e.g. `50 MB`.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_source_size: Option<usize>,
+}
+
+/// Sizes are human-readable, like cache sizes (e.g. `2 GB`), area is in pixels and time
+/// in seconds. Operations exceeding a limit fail with 503 Service Unavailable.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ResourceLimits {
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub memory: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub map: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub disk: Option<usize>,
+    pub area: Option<u64>,
+    pub threads: Option<u64>,
+    /// ImageMagick 7 counts this from the moment each image is created. Older versions count
+    /// it from process start, so a server running longer than the limit would fail every
+    /// request: only set it with ImageMagick 7.
+    pub time: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn limits(&self) -> Vec<(ResourceType, u64)> {
+        let size = |size: Option<usize>| size.map(|size| size as u64);
+        vec![
+            (ResourceType::Memory, size(self.memory)),
+            (ResourceType::Map, size(self.map)),
+            (ResourceType::Disk, size(self.disk)),
+            (ResourceType::Area, self.area),
+            (ResourceType::Thread, self.threads),
+            (ResourceType::Time, self.time),
+        ].into_iter().filter_map(|(resource, limit)| limit.map(|limit| (resource, limit))).collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct Size(#[serde(deserialize_with = "serde_humanize_rs::deserialize")] usize);
+
+fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    Ok(Option::<Size>::deserialize(deserializer)?.map(|Size(size)| size))
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Cache {
     pub dir: String,
@@ -26,10 +91,136 @@ pub struct Cache {
     pub size: usize,
 }
 
+/// Download timeouts in milliseconds.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Timeouts {
+    /// Establishing the connection, including the TLS handshake.
+    pub connect: Option<u64>,
+    /// Receiving the response headers, counted from the start of the download.
+    pub first_byte: Option<u64>,
+    /// The whole download, including the body.
+    pub total: Option<u64>,
+}
+
+impl Timeouts {
+    /// Fills unset timeouts from `defaults`.
+    pub fn or(&self, defaults: &Timeouts) -> Timeouts {
+        Timeouts {
+            connect: self.connect.or(defaults.connect),
+            first_byte: self.first_byte.or(defaults.first_byte),
+            total: self.total.or(defaults.total),
+        }
+    }
+}
+
+/// Restrictions on what downloads can reach. Host patterns are host names,
+/// `*.example.com` wildcards, or IP networks like `10.0.0.0/8`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SecurityConfig {
+    /// Only allow downloads from urls within configured `domains`.
+    #[serde(default)]
+    pub domains_only: bool,
+    /// If set, only these hosts can be downloaded from.
+    pub allow_hosts: Option<Vec<String>>,
+    #[serde(default)]
+    pub deny_hosts: Vec<String>,
+    /// Block loopback, link-local, private and other special addresses, unless the address
+    /// or the host name is listed in `allow_hosts` (wildcards don't exempt private addresses).
+    #[serde(default)]
+    pub block_private: bool,
+}
+
+/// A `domains` entry: urls of the form `prefix:path` are downloaded from `url` + `path`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Domain {
+    pub url: String,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    /// Overrides the global `max_download_size`.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_download_size: Option<usize>,
+    /// Sent with requests to urls within this domain.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub auth: Option<Auth>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Auth {
+    Bearer { token: String },
+    Basic { username: String, password: Option<String> },
+    /// Signs requests with AWS Signature Version 4, for S3-compatible storage. The domain's url
+    /// is the endpoint, including the bucket, e.g. `http://localhost:9000/bucket/`.
+    /// Credentials default to `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`.
+    S3 {
+        region: String,
+        access_key_id: Option<String>,
+        secret_access_key: Option<String>,
+        session_token: Option<String>,
+    },
+}
+
+impl Domain {
+    pub fn new(url: String) -> Self {
+        Domain {
+            url,
+            timeouts: Timeouts::default(),
+            max_download_size: None,
+            headers: HashMap::new(),
+            auth: None,
+        }
+    }
+}
+
+/// Domains can be given as a bare base url, or as a struct with more settings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DomainEntry {
+    Url(String),
+    Domain(Domain),
+}
+
+fn deserialize_domains<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, Domain>, D::Error> {
+    let entries: HashMap<String, DomainEntry> = HashMap::deserialize(deserializer)?;
+    Ok(entries.into_iter().map(|(prefix, entry)| (prefix, match entry {
+        DomainEntry::Url(url) => Domain::new(url),
+        DomainEntry::Domain(domain) => domain,
+    })).collect())
+}
+
+fn default_max_redirects() -> usize {
+    5
+}
+
+fn default_file_threads() -> usize {
+    4
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     #[serde(default)]
     pub image: ImageConfig,
-    pub domains: HashMap<String,String>,
+    #[serde(deserialize_with = "deserialize_domains")]
+    pub domains: HashMap<String,Domain>,
+    /// Timeouts for downloads, which can be overridden per domain.
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    /// Maximum size of a downloaded body, human-readable like cache sizes (e.g. `20 MB`),
+    /// which can be overridden per domain.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_download_size: Option<usize>,
+    #[serde(default)]
+    pub security: SecurityConfig,
+    /// Directories readable with the `file` filter, by name.
+    #[serde(default)]
+    pub roots: HashMap<String, String>,
+    /// Maximum number of redirects followed by a download.
+    #[serde(default = "default_max_redirects")]
+    pub max_redirects: usize,
+    /// Size of the thread pool reading files for the `file` filter.
+    #[serde(default = "default_file_threads")]
+    pub file_threads: usize,
     pub caches: HashMap<String,Cache>
 }
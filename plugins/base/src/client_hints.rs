@@ -0,0 +1,141 @@
+//! Client Hints support for resizing filters: sizes in the `dpr` unit are multiplied by the
+//! device pixel ratio, and an `auto` width is read from the `Sec-CH-Width` request header.
+use std::str;
+use std::collections::HashMap;
+use hyper::Headers;
+use imaginator::prelude::*;
+use imaginator::filter::{Args, Context, Future, Filter, FilterArg, SizeUnit};
+
+/// Hints requested from clients, which responses using them vary on.
+const HINTS: &'static str = "Sec-CH-DPR, Sec-CH-Width, Save-Data";
+
+fn header<'a>(headers: &'a Headers, names: &[&str]) -> Option<&'a str> {
+    names.iter()
+        .filter_map(|name| headers.get_raw(name))
+        .filter_map(|raw| raw.one())
+        .filter_map(|value| str::from_utf8(value).ok())
+        .map(str::trim)
+        .next()
+}
+
+fn save_data(headers: &Headers) -> bool {
+    header(headers, &["Save-Data"]).map_or(false, |value| value.eq_ignore_ascii_case("on"))
+}
+
+/// Device pixel ratio reported by the client, from `Sec-CH-DPR` or the legacy `DPR` header.
+fn client_dpr(headers: &Headers) -> f32 {
+    header(headers, &["Sec-CH-DPR", "DPR"])
+        .and_then(|value| value.parse::<f32>().ok())
+        .filter(|dpr| dpr.is_finite() && *dpr > 0.0)
+        .unwrap_or(1.0)
+}
+
+/// Device pixel ratio used for the response. `Save-Data: on` caps it at 1.
+pub fn dpr(headers: &Headers) -> f32 {
+    let dpr = client_dpr(headers);
+    if save_data(headers) { dpr.min(1.0) } else { dpr }
+}
+
+/// Width of the image on the client in physical pixels, from `Sec-CH-Width`
+/// or the legacy `Width` header.
+pub fn width(headers: &Headers) -> Option<isize> {
+    let width = header(headers, &["Sec-CH-Width", "Width"])
+        .and_then(|value| value.parse::<isize>().ok())
+        .filter(|width| *width > 0)?;
+    // The width already includes the client's pixel ratio, which might have been capped.
+    Some((width as f32 * dpr(headers) / client_dpr(headers)).round() as isize)
+}
+
+/// Adds `values` to the `Vary` header, keeping whatever it already contains.
+pub fn add_vary(response_headers: &mut HashMap<String, String>, values: &str) {
+    let vary = response_headers.entry("Vary".to_owned()).or_insert_with(String::new);
+    for value in values.split(',').map(str::trim).filter(|value| !value.is_empty()) {
+        if !vary.split(',').any(|existing| existing.trim().eq_ignore_ascii_case(value)) {
+            if !vary.is_empty() {
+                vary.push_str(", ");
+            }
+            vary.push_str(value);
+        }
+    }
+}
+
+/// Replaces sizes depending on client hints in the arguments of a resizing filter,
+/// and advertises the hints in the response headers if any were used.
+fn resolve(context: &Context, args: &Args) -> Args {
+    let headers = &context.request_headers;
+    let dpr = dpr(headers);
+    let mut hinted = false;
+    let args = args.iter().enumerate().map(|(i, arg)| match *arg {
+        FilterArg::Int(value, SizeUnit::Dpr) => {
+            hinted = true;
+            FilterArg::Int((value as f32 * dpr).round() as isize, SizeUnit::None)
+        },
+        FilterArg::Float(value, SizeUnit::Dpr) => {
+            hinted = true;
+            FilterArg::Int((value * dpr).round() as isize, SizeUnit::None)
+        },
+        // Without the hint, the width is computed from the height, as with 0.
+        FilterArg::String(ref value) if i == 1 && value == "auto" => {
+            hinted = true;
+            FilterArg::Int(width(headers).unwrap_or(0), SizeUnit::None)
+        },
+        ref arg => arg.clone()
+    }).collect();
+
+    if hinted {
+        let mut response_headers = context.response_headers.borrow_mut();
+        response_headers.insert("Accept-CH".to_owned(), HINTS.to_owned());
+        add_vary(&mut response_headers, HINTS);
+        response_headers.insert("Content-DPR".to_owned(), format!("{}", dpr));
+    }
+    args
+}
+
+/// Resolves client hints in all resizing filters of `filter`, e.g. before it's used as a cache key,
+/// so that responses for different hints don't share an entry.
+pub fn resolve_tree(context: &Context, filter: &Filter) -> Filter {
+    let args: Args = filter.args.iter().map(|arg| match *arg {
+        FilterArg::Img(ref img) => FilterArg::Img(resolve_tree(context, img)),
+        ref arg => arg.clone()
+    }).collect();
+    let args = match filter.name.as_str() {
+        "resize" | "fit-in" | "fill" => resolve(context, &args),
+        _ => args
+    };
+    Filter { name: filter.name.clone(), args }
+}
+
+pub fn resize(context: &mut Context, args: &Args) -> Box<Future> {
+    let args = resolve(context, args);
+    ::resize(context, &args)
+}
+
+pub fn fit_in(context: &mut Context, args: &Args) -> Box<Future> {
+    let args = resolve(context, args);
+    ::fit_in(context, &args)
+}
+
+pub fn fill(context: &mut Context, args: &Args) -> Box<Future> {
+    let args = resolve(context, args);
+    ::fill(context, &args)
+}
+
+#[test]
+fn test_hints() {
+    let mut headers = Headers::new();
+    headers.set_raw("Sec-CH-DPR", "2");
+    headers.set_raw("Sec-CH-Width", "800");
+    assert_eq!(dpr(&headers), 2.0);
+    assert_eq!(width(&headers), Some(800));
+    headers.set_raw("Save-Data", "on");
+    assert_eq!(dpr(&headers), 1.0);
+    assert_eq!(width(&headers), Some(400));
+}
+
+#[test]
+fn test_add_vary() {
+    let mut response_headers = HashMap::new();
+    response_headers.insert("Vary".to_owned(), "Accept, save-data".to_owned());
+    add_vary(&mut response_headers, HINTS);
+    assert_eq!(response_headers["Vary"], "Accept, save-data, Sec-CH-DPR, Sec-CH-Width");
+}
@@ -0,0 +1,86 @@
+//! Connectors for the download client, adding a timeout to establishing connections
+//! and checking the addresses connected to.
+use std::io;
+use std::time::Duration;
+use hyper::Uri;
+use hyper::client::Service;
+use hyper_tls::MaybeHttpsStream;
+use tokio_core::net::TcpStream;
+use futures::{future, Future as FutureTrait};
+use futures::future::Either;
+use tokio_core::reactor::{Handle, Timeout};
+use guard;
+
+pub struct TimeoutConnector<C> {
+    connector: C,
+    handle: Handle,
+    timeout: Option<Duration>,
+}
+
+impl<C> TimeoutConnector<C> {
+    pub fn new(connector: C, handle: &Handle, timeout: Option<Duration>) -> Self {
+        TimeoutConnector {
+            connector,
+            handle: handle.clone(),
+            timeout,
+        }
+    }
+}
+
+impl<C> Service for TimeoutConnector<C>
+    where C: Service<Request = Uri, Error = io::Error>, C::Future: 'static, C::Response: 'static
+{
+    type Request = Uri;
+    type Response = C::Response;
+    type Error = io::Error;
+    type Future = Box<FutureTrait<Item = C::Response, Error = io::Error>>;
+
+    fn call(&self, uri: Uri) -> Self::Future {
+        let connecting = self.connector.call(uri);
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Box::new(connecting)
+        };
+        let timer = match Timeout::new(timeout, &self.handle) {
+            Ok(timer) => timer,
+            Err(e) => return Box::new(future::err(e))
+        };
+        Box::new(connecting.select2(timer).then(|result| match result {
+            Ok(Either::A((connection, _))) => Ok(connection),
+            Ok(Either::B(_)) => Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out")),
+            Err(Either::A((e, _))) | Err(Either::B((e, _))) => Err(e),
+        }))
+    }
+}
+
+/// Checks the address of every connection with `guard::check_address`.
+pub struct GuardConnector<C> {
+    connector: C,
+}
+
+impl<C> GuardConnector<C> {
+    pub fn new(connector: C) -> Self {
+        GuardConnector { connector }
+    }
+}
+
+impl<C> Service for GuardConnector<C>
+    where C: Service<Request = Uri, Response = MaybeHttpsStream<TcpStream>, Error = io::Error>, C::Future: 'static
+{
+    type Request = Uri;
+    type Response = MaybeHttpsStream<TcpStream>;
+    type Error = io::Error;
+    type Future = Box<FutureTrait<Item = MaybeHttpsStream<TcpStream>, Error = io::Error>>;
+
+    fn call(&self, uri: Uri) -> Self::Future {
+        let host = uri.host().unwrap_or("").to_owned();
+        Box::new(self.connector.call(uri).and_then(move |stream| {
+            let peer = match stream {
+                MaybeHttpsStream::Http(ref tcp) => tcp.peer_addr()?,
+                MaybeHttpsStream::Https(ref tls) => tls.get_ref().get_ref().peer_addr()?,
+            };
+            guard::check_address(&host, peer.ip()).map_err(|message| io::Error::new(io::ErrorKind::PermissionDenied, message))?;
+            Ok(stream)
+        }))
+    }
+}
@@ -0,0 +1,158 @@
+use futures::Future as FutureTrait;
+use imaginator::prelude::*;
+use imaginator::img::{ImageFormat, Filter as FilterType};
+use imaginator::filter::{Args, Future, Context};
+use output::TextResult;
+
+const DEFAULT_TILE_SIZE: usize = 254;
+const DEFAULT_OVERLAP: usize = 1;
+
+/// Index of the pyramid level at which the image has its original size.
+/// Every level below it is half the size of the next one, down to 1x1 at level 0.
+pub fn max_level(width: usize, height: usize) -> usize {
+    let size = width.max(height);
+    let mut level = 0;
+    while (1 << level) < size {
+        level += 1;
+    }
+    level
+}
+
+/// Describes where a tile comes from in the source image, and how big it is.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TileGeometry {
+    pub source_x: usize,
+    pub source_y: usize,
+    pub source_width: usize,
+    pub source_height: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+fn tile_range(size: usize, index: usize, tile_size: usize, overlap: usize) -> Option<(usize, usize)> {
+    let start = index.checked_mul(tile_size)?;
+    if start >= size {
+        return None;
+    }
+    let end = (start + tile_size + overlap).min(size);
+    let start = if index > 0 { start - overlap } else { start };
+    Some((start, end))
+}
+
+/// Deep Zoom only knows about jpg and png tiles, so everything that isn't a JPEG is tiled as PNG.
+fn tile_format(format: ImageFormat) -> ImageFormat {
+    match format {
+        ImageFormat::JPEG => ImageFormat::JPEG,
+        _ => ImageFormat::PNG
+    }
+}
+
+fn check_tiling(name: &str, tile_size: isize, overlap: isize) -> Result<(), Error> {
+    if tile_size < 1 || overlap < 0 || overlap >= tile_size {
+        bail!("{}: tile size must be at least 1px, and overlap must be between 0 and the tile size.", name);
+    }
+    Ok(())
+}
+
+pub fn tile_geometry(width: usize, height: usize, level: usize, x: usize, y: usize, tile_size: usize, overlap: usize) -> Result<TileGeometry, Error> {
+    let max_level = max_level(width, height);
+    if level > max_level {
+        bail!("tile: level {} does not exist, the highest level is {}.", level, max_level);
+    }
+    let scale = 1.0 / (1u64 << (max_level - level)) as f64;
+    let level_width = (width as f64 * scale).ceil() as usize;
+    let level_height = (height as f64 * scale).ceil() as usize;
+    let (x0, x1) = tile_range(level_width, x, tile_size, overlap)
+        .ok_or_else(|| format_err!("tile: column {} does not exist at level {}.", x, level))?;
+    let (y0, y1) = tile_range(level_height, y, tile_size, overlap)
+        .ok_or_else(|| format_err!("tile: row {} does not exist at level {}.", y, level))?;
+
+    let source_x = (x0 as f64 / scale).floor() as usize;
+    let source_y = (y0 as f64 / scale).floor() as usize;
+    Ok(TileGeometry {
+        source_x,
+        source_y,
+        source_width: ((x1 as f64 / scale).ceil() as usize).min(width) - source_x,
+        source_height: ((y1 as f64 / scale).ceil() as usize).min(height) - source_y,
+        width: x1 - x0,
+        height: y1 - y0,
+    })
+}
+
+/// tile(img, level, x, y, tile_size?, overlap?)
+///
+/// Only the part of the source covered by the tile is scaled, so the cost doesn't
+/// depend on the size of the pyramid level.
+pub fn tile(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(tile, args, 0, context, Image);
+    let args = args.clone();
+    Box::new(img.and_then(move |mut img| {
+        let level = arg_type!(tile, args, 1, img, isize);
+        let x = arg_type!(tile, args, 2, img, isize);
+        let y = arg_type!(tile, args, 3, img, isize);
+        let tile_size = if args.len() > 4 { arg_type!(tile, args, 4, img, isize) } else { DEFAULT_TILE_SIZE as isize };
+        let overlap = if args.len() > 5 { arg_type!(tile, args, 5, img, isize) } else { DEFAULT_OVERLAP as isize };
+        if level < 0 || x < 0 || y < 0 {
+            bail!("tile: level, x and y cannot be negative.");
+        }
+        check_tiling("tile", tile_size, overlap)?;
+        let tile = tile_geometry(img.width(), img.height(), level as usize, x as usize, y as usize, tile_size as usize, overlap as usize)?;
+        img.crop(tile.source_x as isize, tile.source_y as isize, tile.source_width, tile.source_height)?;
+        img.reset_page()?;
+        if tile.width != tile.source_width || tile.height != tile.source_height {
+            img.resize(tile.width, tile.height, &FilterType::default())?;
+        }
+        let format = tile_format(img.format()?);
+        if img.format()? != format {
+            img.set_format(&format)?;
+        }
+        Ok(img.into())
+    }))
+}
+
+/// dzi(img, tile_size?, overlap?)
+///
+/// Returns the Deep Zoom descriptor of the image. Tiles are jpg for JPEG sources and png otherwise,
+/// which is what `tile` produces.
+pub fn dzi(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(dzi, args, 0, context, Image);
+    let args = args.clone();
+    Box::new(img.and_then(move |img| {
+        let tile_size = if args.len() > 1 { arg_type!(dzi, args, 1, img, isize) } else { DEFAULT_TILE_SIZE as isize };
+        let overlap = if args.len() > 2 { arg_type!(dzi, args, 2, img, isize) } else { DEFAULT_OVERLAP as isize };
+        check_tiling("dzi", tile_size, overlap)?;
+        let format = match tile_format(img.format()?) {
+            ImageFormat::JPEG => "jpg",
+            _ => "png"
+        };
+        let xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"{}\" Overlap=\"{}\" TileSize=\"{}\">\
+             <Size Width=\"{}\" Height=\"{}\"/></Image>",
+            format, overlap, tile_size, img.width(), img.height()
+        );
+        Ok(Box::new(TextResult::with_content_type(xml, ContentType("application/xml".parse().unwrap()))).into())
+    }))
+}
+
+#[test]
+fn test_max_level() {
+    assert_eq!(max_level(1, 1), 0);
+    assert_eq!(max_level(1024, 768), 10);
+    assert_eq!(max_level(1025, 10), 11);
+}
+
+#[test]
+fn test_tile_geometry() {
+    // Level 9 of a 1000x600 image is 500x300.
+    assert_eq!(tile_geometry(1000, 600, 9, 1, 1, 254, 1).unwrap(), TileGeometry {
+        source_x: 506,
+        source_y: 506,
+        source_width: 494,
+        source_height: 94,
+        width: 247,
+        height: 47,
+    });
+    assert!(tile_geometry(1000, 600, 9, 2, 0, 254, 1).is_err());
+    assert!(tile_geometry(1000, 600, 9, usize::max_value(), 0, 254, 1).is_err());
+}
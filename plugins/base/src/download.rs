@@ -1,12 +1,25 @@
+use std::io;
 use std::rc::Rc;
+use std::time::Duration;
+use hyper;
 use hyper_tls::HttpsConnector;
-use hyper::{Client, StatusCode};
-use futures::{Future as FutureTrait, Stream};
+use hyper::{Client, Method, StatusCode};
+use hyper::client::HttpConnector;
+use hyper::header::{Authorization, Basic, Bearer, ContentLength, Location};
+use tokio_core::reactor::Handle;
+use futures::{future, Future as FutureTrait, Stream};
 use imaginator::img;
-use imaginator::filter::{Args, Future, ErrorResponse, Context};
+use imaginator::filter::{Args, Future, ErrorResponse, Context, error_response};
 use imaginator::prelude::*;
 use imaginator::cfg::config;
+use imaginator::timeout::{timed_out, with_timeout};
+use cfg::{Auth, Domain};
+use connector::{GuardConnector, TimeoutConnector};
+use guard::{self, forbidden};
+use s3;
+use chrono::Utc;
 use ::Config;
+use limits::{self, too_large};
 
 #[derive(PartialEq,Eq,Debug,Clone,Fail)]
 #[fail(display="Url {} returned {}.", url, status_code)]
@@ -35,6 +48,15 @@ impl FilterResult for DownloadError {
     }
 }
 
+impl DownloadResult {
+    pub fn new(buffer: Vec<u8>, dpi: Option<f64>) -> Self {
+        DownloadResult {
+            dpi,
+            buffer: Rc::new(buffer)
+        }
+    }
+}
+
 impl FilterResult for DownloadResult {
     fn content_type(&self) -> Result<ContentType, Error> {
         let mut image = img::Image::new(None, self.dpi)?;
@@ -55,6 +77,7 @@ impl FilterResult for DownloadResult {
     }
 
     fn image(self: Box<Self>) -> Result<img::Image, Error> {
+        limits::check_source(&*self.buffer, self.dpi)?;
         let image = img::Image::new(&*self.buffer, self.dpi)?;
         let image_format = image.format()?;
         if let Some(ref formats) = config::<Config>().unwrap().image.supported_formats {
@@ -66,24 +89,170 @@ impl FilterResult for DownloadResult {
     }
 }
 
-pub fn decode_url(url: &str) -> String {
+/// Resolves urls of the form `prefix:path` using `domains`, and returns the matching domain.
+pub fn resolve_url(url: &str) -> (String, Option<&'static Domain>) {
     let mut split = url.splitn(2, ':');
     if let Some(domain) = config::<Config>().unwrap().domains.get(split.next().unwrap()) {
-        let mut url = domain.clone();
-        url.push_str(split.next().unwrap());
-        url
+        let mut resolved = domain.url.clone();
+        resolved.push_str(split.next().unwrap());
+        (resolved, Some(domain))
+    } else {
+        (url.to_owned(), None)
+    }
+}
+
+pub fn decode_url(url: &str) -> String {
+    resolve_url(url).0
+}
+
+fn timeout<F>(future: F, timeout: Option<u64>, handle: &Handle, step: String) -> Box<FutureTrait<Item = F::Item, Error = Error>>
+    where F: FutureTrait<Error = Error> + 'static, F::Item: 'static
+{
+    match timeout {
+        Some(ms) => with_timeout(future, Duration::from_millis(ms), handle, move || step),
+        None => Box::new(future)
+    }
+}
+
+type DownloadClient = Client<TimeoutConnector<GuardConnector<HttpsConnector<HttpConnector>>>>;
+
+/// Whether `location` starts with a scheme (RFC 3986: a letter, then letters, digits, `+`, `-`
+/// or `.`, followed by `:`), which makes it an absolute url.
+fn has_scheme(location: &str) -> bool {
+    match location.find(':') {
+        Some(end) => {
+            let scheme = &location[..end];
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        },
+        None => false
+    }
+}
+
+/// Resolves a `Location` header against the url which returned it.
+fn resolve_location(base: &str, location: &str) -> Result<String, Error> {
+    if has_scheme(location) {
+        return Ok(location.to_owned());
+    }
+    let uri: hyper::Uri = base.parse()?;
+    let scheme = uri.scheme().unwrap_or("http");
+    Ok(if location.starts_with("//") {
+        format!("{}:{}", scheme, location)
+    } else if location.starts_with('/') {
+        format!("{}://{}{}", scheme, uri.authority().unwrap_or(""), location)
+    } else if location.starts_with('?') {
+        format!("{}://{}{}{}", scheme, uri.authority().unwrap_or(""), uri.path(), location)
     } else {
-        url.to_owned()
+        let path = uri.path();
+        format!("{}://{}{}{}", scheme, uri.authority().unwrap_or(""), &path[..path.rfind('/').map_or(0, |i| i + 1)], location)
+    })
+}
+
+/// A download which was redirected more than `max_redirects` times. The loop is upstream,
+/// so it's returned as 502 Bad Gateway.
+fn too_many_redirects(url: &str) -> Error {
+    error_response(StatusCode::BadGateway, format!("Url {} was redirected too many times.", url))
+}
+
+/// Builds a request, with the domain's headers and credentials if `url` is within it.
+fn request(uri: hyper::Uri, url: &str, domain: Option<&Domain>) -> Result<hyper::Request, Error> {
+    let mut request = hyper::Request::new(Method::Get, uri.clone());
+    // Redirects can lead to other hosts, which mustn't receive the credentials.
+    if let Some(domain) = domain.filter(|domain| guard::within(&domain.url, url)) {
+        let headers = request.headers_mut();
+        for (name, value) in &domain.headers {
+            headers.set_raw(name.clone(), value.clone());
+        }
+        match domain.auth {
+            Some(Auth::Bearer { ref token }) => headers.set(Authorization(Bearer { token: token.clone() })),
+            Some(Auth::Basic { ref username, ref password }) => headers.set(Authorization(Basic {
+                username: username.clone(),
+                password: password.clone()
+            })),
+            Some(Auth::S3 { ref region, ref access_key_id, ref secret_access_key, ref session_token }) => {
+                let credentials = s3::Credentials::new(access_key_id, secret_access_key, session_token)?;
+                for (name, value) in s3::sign(&uri, region, &credentials, Utc::now()) {
+                    headers.set_raw(name, value);
+                }
+            },
+            None => {}
+        }
+    }
+    Ok(request)
+}
+
+/// Requests `url`, following up to `redirects` redirects, and failing with 502 Bad Gateway
+/// after that. Every url is checked with `guard::check_url`.
+/// Returns the final url along with the response.
+fn get(client: Rc<DownloadClient>, url: String, domain: Option<&'static Domain>, redirects: usize) -> Box<FutureTrait<Item = (String, hyper::Response), Error = Error>> {
+    let uri = match guard::check_url(&url).and_then(|_| url.parse::<hyper::Uri>().map_err(Error::from)) {
+        Ok(uri) => uri,
+        Err(e) => return Box::new(future::err(e))
+    };
+    let request = match request(uri, &url, domain) {
+        Ok(request) => request,
+        Err(e) => return Box::new(future::err(e))
+    };
+    let connect_url = url.clone();
+    Box::new(client.request(request).map_err(move |e| match e {
+        hyper::Error::Io(ref e) if e.kind() == io::ErrorKind::TimedOut => timed_out(format!("connecting to {}", connect_url)),
+        hyper::Error::Io(ref e) if e.kind() == io::ErrorKind::PermissionDenied => forbidden(format!("Url {}: {}", connect_url, e)),
+        e => Error::from(e)
+    }).and_then(move |res| -> Box<FutureTrait<Item = (String, hyper::Response), Error = Error>> {
+        let location = match res.status() {
+            StatusCode::MovedPermanently | StatusCode::Found | StatusCode::SeeOther
+                | StatusCode::TemporaryRedirect | StatusCode::PermanentRedirect =>
+                res.headers().get::<Location>().map(|location| location.to_string()),
+            _ => None
+        };
+        match location {
+            Some(_) if redirects == 0 => Box::new(future::err(too_many_redirects(&url))),
+            Some(ref location) => match resolve_location(&url, location) {
+                Ok(next) => get(client, next, domain, redirects - 1),
+                Err(e) => Box::new(future::err(e))
+            },
+            None => Box::new(future::ok((url, res)))
+        }
+    }))
+}
+
+/// Downloads `url`, returning the final url after redirects along with the body.
+/// Rejects a response whose announced Content-Length exceeds `max_size`, before reading its body.
+fn check_content_length(url: &str, length: Option<u64>, max_size: Option<usize>) -> Result<(), Error> {
+    match (length, max_size) {
+        (Some(length), Some(max_size)) if length > max_size as u64 =>
+            Err(too_large(format!("Url {} returned {} bytes, exceeding the limit of {} bytes.", url, length, max_size))),
+        _ => Ok(())
+    }
+}
+
+/// Appends a chunk of a streamed body, failing as soon as the body would exceed `max_size`.
+fn append_chunk(url: &str, body: &mut Vec<u8>, chunk: &[u8], max_size: Option<usize>) -> Result<(), Error> {
+    if let Some(max_size) = max_size {
+        if body.len() + chunk.len() > max_size {
+            return Err(too_large(format!("Url {} returned more than {} bytes.", url, max_size)));
+        }
     }
+    body.extend_from_slice(chunk);
+    Ok(())
 }
 
-pub fn download_url(context: &Context, url: &str) -> Box<FutureTrait<Item = Vec<u8>, Error = Error>> {
+pub fn download_url(context: &Context, url: &str, domain: Option<&'static Domain>) -> Box<FutureTrait<Item = (String, Vec<u8>), Error = Error>> {
+    let cfg = config::<Config>().unwrap();
+    let timeouts = match domain {
+        Some(domain) => domain.timeouts.or(&cfg.timeouts),
+        None => cfg.timeouts.clone()
+    };
     let url = url.to_owned();
     let handle = context.remote.handle().unwrap();
-    let client = Client::configure().connector(HttpsConnector::new(1, &handle).unwrap()).build(&handle);
-    let parsed_url = url.parse().into_future().from_err();
-    let response = parsed_url.and_then(move |url| client.get(url).from_err());
-    let body = response.and_then(move |res| {
+    let connector = GuardConnector::new(HttpsConnector::new(1, &handle).unwrap());
+    let connector = TimeoutConnector::new(connector, &handle, timeouts.connect.map(Duration::from_millis));
+    let client = Rc::new(Client::configure().connector(connector).build(&handle));
+    let response = get(client, url.clone(), domain, cfg.max_redirects);
+    let response = timeout(response, timeouts.first_byte, &handle, format!("waiting for a response from {}", url));
+    let max_size = domain.and_then(|domain| domain.max_download_size).or(cfg.max_download_size);
+    let body = response.and_then(move |(url, res)| {
+        // Errors, including S3's 403 and 404, are passed on to the client with the same status.
         if res.status() != StatusCode::Ok {
             return Err(Error::from(
                 ErrorResponse(Box::new(
@@ -91,28 +260,35 @@ pub fn download_url(context: &Context, url: &str) -> Box<FutureTrait<Item = Vec<
                 ))
             )).into_future();
         }
-        Ok(res).into_future()
-    }).and_then(|res| {
-        res.body().concat2().from_err()
+        let length = res.headers().get::<ContentLength>().map(|&ContentLength(length)| length);
+        if let Err(err) = check_content_length(&url, length, max_size) {
+            return Err(err).into_future();
+        }
+        Ok((url, res)).into_future()
+    }).and_then(move |(url, res)| {
+        // Content-Length might be missing or wrong, so the limit is checked while streaming, too.
+        let body_url = url.clone();
+        res.body().from_err().fold(Vec::new(), move |mut body, chunk| {
+            append_chunk(&body_url, &mut body, &chunk, max_size).map(|_| body)
+        }).map(move |body| (url, body))
     });
-    Box::new(body.map(move |body| body.to_vec()))
+    timeout(body, timeouts.total, &handle, format!("downloading {}", url))
 }
 
 pub fn filter(context: &mut Context, args: &Args) -> Box<Future> {
     let url_arg = arg_type!(download, args, 0, String);
-    context.log_filters_header.as_ref().map(|header_name|
-        context.response_headers.entry(header_name.clone()).and_modify(|value| {
-            value.push_str("(");
-            value.push_str(url_arg.splitn(2, ':').next().unwrap());
-            value.push_str(")");
-        })
-    );
-    let url = decode_url(&url_arg);
+    let log_entry = context.log_details(&format!("({})", url_arg.splitn(2, ':').next().unwrap()));
+    let (url, domain) = resolve_url(&url_arg);
     let dpi = if args.len() > 1 {
         Some(arg_type!(download, args, 1, isize) as f64)
     } else { None };
-    let body = download_url(context, &url);
-    let img = body.and_then(move |body| {
+    let body = download_url(context, &url, domain);
+    let filter_log = context.filter_log.clone();
+    let img = body.and_then(move |(final_url, body)| {
+        // Reported right after the download, rather than after whatever finished before it.
+        if let (true, Some(index)) = (final_url != url, log_entry) {
+            filter_log.append(index, &format!(",redirect({})", final_url));
+        }
         let body = (&*body).to_vec();
         Ok(DownloadResult {
             dpi: dpi,
@@ -121,3 +297,58 @@ pub fn filter(context: &mut Context, args: &Args) -> Box<Future> {
     });
     Box::new(img.map(|img| Box::new(img).into()))
 }
+
+#[test]
+fn test_resolve_location() {
+    let base = "https://example.com/images/a.jpg";
+    assert_eq!(resolve_location(base, "http://cdn.example.com/a.jpg").unwrap(), "http://cdn.example.com/a.jpg");
+    assert_eq!(resolve_location(base, "//cdn.example.com/a.jpg").unwrap(), "https://cdn.example.com/a.jpg");
+    assert_eq!(resolve_location(base, "/b.jpg").unwrap(), "https://example.com/b.jpg");
+    assert_eq!(resolve_location(base, "b.jpg").unwrap(), "https://example.com/images/b.jpg");
+    assert_eq!(resolve_location(base, "/r?next=http://x").unwrap(), "https://example.com/r?next=http://x");
+    assert_eq!(resolve_location(base, "b.jpg?next=http://x").unwrap(), "https://example.com/images/b.jpg?next=http://x");
+    assert_eq!(resolve_location(base, "?v=2").unwrap(), "https://example.com/images/a.jpg?v=2");
+}
+
+#[test]
+fn test_credentials_stay_within_domain() {
+    let mut domain = Domain::new("https://origin.example.com/images/".to_owned());
+    domain.auth = Some(Auth::Bearer { token: "secret".to_owned() });
+    domain.headers.insert("X-Api-Key".to_owned(), "secret".to_owned());
+    let base = "https://origin.example.com/images/a.jpg";
+    let with_credentials = |url: &str| {
+        let request = request(url.parse().unwrap(), url, Some(&domain)).unwrap();
+        request.headers().get::<Authorization<Bearer>>().is_some() || request.headers().get_raw("X-Api-Key").is_some()
+    };
+    assert!(with_credentials(base));
+    for location in &["https://origin.example.com.attacker.net/images/a.jpg", "https://origin.example.com@attacker.net/images/a.jpg", "http://origin.example.com/images/a.jpg"] {
+        let next = resolve_location(base, location).unwrap();
+        assert!(!with_credentials(&next), "credentials sent to {}", next);
+    }
+}
+
+#[test]
+fn test_check_content_length() {
+    let url = "https://example.com/a.jpg";
+    assert!(check_content_length(url, Some(100), Some(100)).is_ok());
+    assert!(check_content_length(url, None, Some(100)).is_ok());
+    assert!(check_content_length(url, Some(1 << 40), None).is_ok());
+    let error = check_content_length(url, Some(101), Some(100)).unwrap_err();
+    let response = error.downcast_ref::<ErrorResponse>().expect("should be an error response");
+    assert_eq!(response.status_code(), StatusCode::PayloadTooLarge);
+}
+
+#[test]
+fn test_append_chunk() {
+    let url = "https://example.com/a.jpg";
+    let mut body = Vec::new();
+    append_chunk(url, &mut body, &[0; 60], Some(100)).unwrap();
+    append_chunk(url, &mut body, &[0; 40], Some(100)).unwrap();
+    assert_eq!(body.len(), 100);
+    let error = append_chunk(url, &mut body, &[0], Some(100)).unwrap_err();
+    let response = error.downcast_ref::<ErrorResponse>().expect("should be an error response");
+    assert_eq!(response.status_code(), StatusCode::PayloadTooLarge);
+    assert_eq!(body.len(), 100);
+    append_chunk(url, &mut body, &[0; 1000], None).unwrap();
+    assert_eq!(body.len(), 1100);
+}
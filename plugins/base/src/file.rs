@@ -0,0 +1,115 @@
+//! `file(root:path, dpi?)` reads an image from one of the directories configured in `roots`.
+//!
+//! Paths are resolved with symlinks followed, and rejected unless they stay within the root.
+//! As the tree might change between resolving and opening a path, the opened file is checked
+//! against a second resolution, too.
+//! Files are read on a thread pool, so that the reactor isn't blocked.
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use hyper::StatusCode;
+use futures::{future, Future as FutureTrait};
+use futures_cpupool::CpuPool;
+use imaginator::filter::{Args, Context, ErrorResponse, Future};
+use imaginator::prelude::*;
+use imaginator::cfg::config;
+use download::{DownloadError, DownloadResult};
+use guard::forbidden;
+use ::Config;
+
+lazy_static! {
+    static ref POOL: CpuPool = CpuPool::new(config::<Config>().unwrap().file_threads);
+}
+
+/// Resolves `path` within `root`, rejecting paths which escape it.
+/// A root which can't be resolved is a configuration error rather than a missing file.
+fn jail(root: &Path, path: &str) -> Result<PathBuf, Error> {
+    let relative = Path::new(path);
+    if relative.components().any(|component| match component {
+        Component::Normal(_) | Component::CurDir => false,
+        _ => true
+    }) {
+        return Err(forbidden(format!("Path {} is not allowed.", path)));
+    }
+    let root = fs::canonicalize(root).map_err(|e| format_err!("Root {} is not accessible: {}", root.display(), e))?;
+    let resolved = fs::canonicalize(root.join(relative))?;
+    // Symlinks inside the root might point outside of it.
+    if !resolved.starts_with(&root) {
+        return Err(forbidden(format!("Path {} is not allowed.", path)));
+    }
+    Ok(resolved)
+}
+
+fn read(root: &Path, path: &str) -> Result<Vec<u8>, Error> {
+    let resolved = jail(root, path)?;
+    let mut file = File::open(&resolved)?;
+    let metadata = file.metadata()?;
+    // A directory on the way might have been replaced by a symlink after it was resolved,
+    // so the opened file has to be the one the path resolves to now.
+    let current = fs::metadata(jail(root, path)?)?;
+    if (metadata.dev(), metadata.ino()) != (current.dev(), current.ino()) {
+        return Err(forbidden(format!("Path {} changed while it was opened.", path)));
+    }
+    // Directories (including the root itself) are reported like missing files.
+    if !metadata.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "Not a file.").into());
+    }
+    let mut buffer = vec![];
+    file.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn io_error(url: String, error: Error) -> Error {
+    let status_code = match error.downcast_ref::<io::Error>().map(io::Error::kind) {
+        Some(io::ErrorKind::NotFound) => StatusCode::NotFound,
+        Some(io::ErrorKind::PermissionDenied) => StatusCode::Forbidden,
+        _ => return error
+    };
+    Error::from(ErrorResponse(Box::new(DownloadError { url, status_code })))
+}
+
+pub fn filter(context: &mut Context, args: &Args) -> Box<Future> {
+    let url = arg_type!(file, args, 0, String);
+    let dpi = if args.len() > 1 {
+        Some(arg_type!(file, args, 1, isize) as f64)
+    } else { None };
+    let (root, path) = {
+        let mut split = url.splitn(2, ':');
+        (split.next().unwrap().to_owned(), split.next().unwrap_or("").to_owned())
+    };
+    context.log_details(&format!("({})", root));
+    let root = match config::<Config>().unwrap().roots.get(&root) {
+        Some(root) => PathBuf::from(root),
+        None => return Box::new(future::err(format_err!("No such root: {}", root)))
+    };
+    let body = POOL.spawn_fn(move || read(&root, &path)).map_err(move |e| io_error(url, e));
+    Box::new(body.map(move |body| Box::new(DownloadResult::new(body, dpi)).into()))
+}
+
+#[test]
+fn test_jail() {
+    use std::os::unix::fs::symlink;
+    let dir = ::std::env::temp_dir().join(format!("imaginator-file-test-{}", ::std::process::id()));
+    let root = dir.join("root");
+    fs::create_dir_all(root.join("images")).unwrap();
+    fs::write(root.join("images/a.jpg"), b"image").unwrap();
+    fs::write(dir.join("secret.txt"), b"secret").unwrap();
+    symlink(dir.join("secret.txt"), root.join("escape.jpg")).unwrap();
+    symlink(root.join("images/a.jpg"), root.join("link.jpg")).unwrap();
+
+    assert!(jail(&root, "../secret.txt").is_err());
+    assert!(jail(&root, "/etc/passwd").is_err());
+    assert!(jail(&root, "escape.jpg").is_err());
+    assert_eq!(read(&root, "link.jpg").unwrap(), b"image");
+    assert_eq!(read(&root, "./images/a.jpg").unwrap(), b"image");
+    for path in &["", "images", "missing.jpg"] {
+        let error = io_error(path.to_string(), read(&root, path).unwrap_err());
+        let response = error.downcast_ref::<ErrorResponse>().expect("should be an error response");
+        assert_eq!(response.status_code(), StatusCode::NotFound);
+    }
+    // A missing root is a server error, not a missing file.
+    let error = io_error("a.jpg".to_owned(), read(&dir.join("missing"), "a.jpg").unwrap_err());
+    assert!(error.downcast_ref::<ErrorResponse>().is_none());
+    fs::remove_dir_all(&dir).unwrap();
+}
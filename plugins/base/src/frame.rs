@@ -0,0 +1,79 @@
+use std::cmp::max;
+use imaginator::prelude::*;
+use imaginator::img::{Image, AlphaChannel, CompositeOperator};
+use mask::ensure_alpha_format;
+
+/// Grows the canvas by the given number of pixels on each side, filling it with `color`.
+pub fn pad_image(img: &mut Image, top: isize, right: isize, bottom: isize, left: isize, color: &str) -> Result<(), Error> {
+    if top < 0 || right < 0 || bottom < 0 || left < 0 {
+        bail!("Padding cannot be negative.");
+    }
+    let (w, h) = (img.width(), img.height());
+    img.set_background_color(color)?;
+    img.extend(-left, -top, w + (left + right) as usize, h + (top + bottom) as usize)
+}
+
+image_filter!(pad(img: Image, top: isize, right: isize, bottom: isize, left: isize, color: String) {
+    pad_image(&mut img, top, right, bottom, left, &color)?;
+});
+
+image_filter!(border(img: Image, width: isize, color: String) {
+    pad_image(&mut img, width, width, width, width, &color)?;
+});
+
+/// Layout of `shadow`: the image is padded so that the shadow (with `margin` pixels around it
+/// for the blur to fade out) fits, and the shadow is placed at `x`, `y` of the padded canvas.
+#[derive(Debug, PartialEq)]
+struct ShadowGeometry {
+    margin: isize,
+    /// Padding of the image, as `(top, right, bottom, left)`.
+    padding: (isize, isize, isize, isize),
+    x: isize,
+    y: isize
+}
+
+fn shadow_geometry(offset_x: isize, offset_y: isize, blur: f32) -> Result<ShadowGeometry, Error> {
+    if blur.is_nan() || blur < 0.0 {
+        bail!("shadow: blur cannot be negative.");
+    }
+    // Gaussian blur is practically invisible beyond two sigmas.
+    let margin = (blur * 2.0).ceil() as isize;
+    let left = max(0, margin - offset_x);
+    let top = max(0, margin - offset_y);
+    let right = max(0, margin + offset_x);
+    let bottom = max(0, margin + offset_y);
+    Ok(ShadowGeometry {
+        margin,
+        padding: (top, right, bottom, left),
+        x: left + offset_x - margin,
+        y: top + offset_y - margin
+    })
+}
+
+image_filter!(shadow(img: Image, offset_x: isize, offset_y: isize, blur: f32, color: String) {
+    let geometry = shadow_geometry(offset_x, offset_y, blur)?;
+    let (w, h) = (img.width(), img.height());
+    let margin = geometry.margin;
+
+    img.set_alpha_channel(&AlphaChannel::Set)?;
+    let mut shadow = Image::solid(w, h, &color)?;
+    shadow.compose(&CompositeOperator::DstIn, &img, 0, 0)?;
+    shadow.set_background_color("none")?;
+    shadow.extend(-margin, -margin, w + 2 * margin as usize, h + 2 * margin as usize)?;
+    if blur > 0.0 {
+        shadow.blur(blur as f64)?;
+    }
+
+    let (top, right, bottom, left) = geometry.padding;
+    pad_image(&mut img, top, right, bottom, left, "none")?;
+    img.compose(&CompositeOperator::DstOver, &shadow, geometry.x, geometry.y)?;
+    ensure_alpha_format(&mut img)?;
+});
+
+#[test]
+fn test_shadow_geometry() {
+    assert_eq!(shadow_geometry(5, 5, 2.0).unwrap(), ShadowGeometry { margin: 4, padding: (0, 9, 9, 0), x: 1, y: 1 });
+    assert_eq!(shadow_geometry(-3, 0, 0.0).unwrap(), ShadowGeometry { margin: 0, padding: (0, 0, 0, 3), x: 0, y: 0 });
+    assert!(shadow_geometry(0, 0, -1.0).is_err());
+    assert!(shadow_geometry(0, 0, -0.1).is_err());
+}
@@ -0,0 +1,113 @@
+use futures::future;
+use imaginator::prelude::*;
+use imaginator::img::{Image, ImageFormat, NoiseType};
+use imaginator::filter::{Args, Future, Context};
+use imaginator::cfg::config;
+use ::Config;
+
+fn check_size(width: isize, height: isize) -> Result<(usize, usize), Error> {
+    let cfg = &config::<Config>().unwrap().image;
+    check_size_within(width, height, cfg.max_width, cfg.max_height)
+}
+
+fn check_size_within(width: isize, height: isize, max_width: Option<isize>, max_height: Option<isize>) -> Result<(usize, usize), Error> {
+    if width < 1 || height < 1 {
+        bail!("Generated image width/height is less than 1px!");
+    }
+    if let Some(max_w) = max_width {
+        if width > max_w {
+            bail!("Cannot generate an image {} pixels wide, because it would exceed the maximum width of {} pixels.", width, max_w);
+        }
+    }
+    if let Some(max_h) = max_height {
+        if height > max_h {
+            bail!("Cannot generate an image {} pixels high, because it would exceed the maximum height of {} pixels.", height, max_h);
+        }
+    }
+    Ok((width as usize, height as usize))
+}
+
+/// Builds a pseudo-image spec like `gradient:red-blue`. Colors are passed to ImageMagick
+/// verbatim, so characters which would end the color or start another part of the spec are
+/// rejected.
+fn gradient_spec(kind: &str, from: &str, to: &str) -> Result<String, Error> {
+    for color in &[from, to] {
+        if color.is_empty() || color.contains(|c| c == '-' || c == ':' || c == '[' || c == ']') {
+            bail!("{}: invalid color: {}", kind, color);
+        }
+    }
+    Ok(format!("{}:{}-{}", kind, from, to))
+}
+
+/// Generated images have no format of their own, so we default to PNG.
+fn result(img: Result<Image, Error>) -> Box<Future> {
+    Box::new(future::result(img.and_then(|mut img| {
+        img.set_format(&ImageFormat::PNG)?;
+        Ok(Box::new(img).into())
+    })))
+}
+
+pub fn solid(_: &mut Context, args: &Args) -> Box<Future> {
+    let width = arg_type!(solid, args, 0, isize);
+    let height = arg_type!(solid, args, 1, isize);
+    let color = arg_type!(solid, args, 2, String);
+    result(check_size(width, height).and_then(|(w, h)| Image::solid(w, h, &color)))
+}
+
+pub fn linear_gradient(_: &mut Context, args: &Args) -> Box<Future> {
+    let width = arg_type!(linear_gradient, args, 0, isize);
+    let height = arg_type!(linear_gradient, args, 1, isize);
+    let from = arg_type!(linear_gradient, args, 2, String);
+    let to = arg_type!(linear_gradient, args, 3, String);
+    // Like in CSS, 180 degrees means a top-to-bottom gradient.
+    let angle = if args.len() > 4 {
+        arg_type!(linear_gradient, args, 4, isize)
+    } else { 180 };
+    result(check_size(width, height).and_then(|(w, h)| {
+        let angle = angle.to_string();
+        Image::pseudo(w, h, &gradient_spec("gradient", &from, &to)?, &[("gradient:angle", &angle)])
+    }))
+}
+
+pub fn radial_gradient(_: &mut Context, args: &Args) -> Box<Future> {
+    let width = arg_type!(radial_gradient, args, 0, isize);
+    let height = arg_type!(radial_gradient, args, 1, isize);
+    let from = arg_type!(radial_gradient, args, 2, String);
+    let to = arg_type!(radial_gradient, args, 3, String);
+    result(check_size(width, height).and_then(|(w, h)| {
+        Image::pseudo(w, h, &gradient_spec("radial-gradient", &from, &to)?, &[])
+    }))
+}
+
+pub fn noise(_: &mut Context, args: &Args) -> Box<Future> {
+    let width = arg_type!(noise, args, 0, isize);
+    let height = arg_type!(noise, args, 1, isize);
+    let noise_type = if args.len() > 2 {
+        arg_type!(noise, args, 2, String)
+    } else { "random".to_owned() };
+    result(check_size(width, height).and_then(|(w, h)| {
+        let noise_type: NoiseType = noise_type.parse()?;
+        let mut img = Image::solid(w, h, "gray50")?;
+        img.add_noise(&noise_type, 1.0)?;
+        Ok(img)
+    }))
+}
+
+#[test]
+fn test_check_size_within() {
+    assert_eq!(check_size_within(100, 50, None, None).unwrap(), (100, 50));
+    assert_eq!(check_size_within(100, 50, Some(100), Some(50)).unwrap(), (100, 50));
+    assert!(check_size_within(0, 50, None, None).is_err());
+    assert!(check_size_within(100, -1, None, None).is_err());
+    assert!(check_size_within(101, 50, Some(100), None).is_err());
+    assert!(check_size_within(100, 51, None, Some(50)).is_err());
+}
+
+#[test]
+fn test_gradient_spec() {
+    assert_eq!(gradient_spec("gradient", "red", "#00f").unwrap(), "gradient:red-#00f");
+    assert_eq!(gradient_spec("radial-gradient", "#0008", "white").unwrap(), "radial-gradient:#0008-white");
+    assert!(gradient_spec("gradient", "red-green", "blue").is_err());
+    assert!(gradient_spec("gradient", "red", "").is_err());
+    assert!(gradient_spec("gradient", "red", "blue[1]").is_err());
+}
@@ -0,0 +1,203 @@
+//! Protection against server-side request forgery: restricting which hosts downloads
+//! can reach, and blocking private addresses once a connection is established.
+//!
+//! Addresses are checked on the connected socket rather than on a separate DNS lookup,
+//! so a host can't resolve to a public address for the check and a private one for the download.
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use hyper::{StatusCode, Uri};
+use imaginator::filter::error_response;
+use imaginator::prelude::*;
+use imaginator::cfg::config;
+use ::Config;
+use cfg::{Domain, SecurityConfig};
+
+/// A download which isn't allowed, returned to the client as 403 Forbidden.
+pub fn forbidden(message: String) -> Error {
+    error_response(StatusCode::Forbidden, message)
+}
+
+/// Parses `ip` or `ip/prefix` patterns.
+fn network(pattern: &str) -> Option<(IpAddr, u32)> {
+    let mut split = pattern.splitn(2, '/');
+    let ip: IpAddr = split.next()?.parse().ok()?;
+    let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = match split.next() {
+        Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= max_prefix)?,
+        None => max_prefix
+    };
+    Some((ip, prefix))
+}
+
+fn in_network(ip: IpAddr, (network, prefix): (IpAddr, u32)) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = if prefix == 0 { 0 } else { !0u32 << (32 - prefix) };
+            u32::from(ip) & mask == u32::from(network) & mask
+        },
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = if prefix == 0 { 0 } else { !0u128 << (128 - prefix) };
+            u128::from(ip) & mask == u128::from(network) & mask
+        },
+        _ => false
+    }
+}
+
+/// Patterns are host names, `*.example.com` wildcards matching subdomains, or IP networks.
+fn matches_host(pattern: &str, host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']').trim_end_matches('.').to_lowercase();
+    let pattern = pattern.to_lowercase();
+    if pattern.starts_with("*.") {
+        host.ends_with(&pattern[1..])
+    } else if let Some(network) = network(&pattern) {
+        host.parse().map(|ip| in_network(ip, network)).unwrap_or(false)
+    } else {
+        host == pattern
+    }
+}
+
+fn matches_ip(pattern: &str, ip: IpAddr) -> bool {
+    network(pattern).map_or(false, |network| in_network(ip, network))
+}
+
+/// Loopback, link-local, private (RFC 1918, RFC 6598 and unique local), unspecified ("this network"),
+/// benchmarking, IETF protocol assignment, broadcast and multicast addresses. IPv6 addresses
+/// embedding an IPv4 address (mapped, compatible and NAT64) are checked by that address.
+pub fn is_private(ip: IpAddr) -> bool {
+    const V4_NETWORKS: &[&str] = &["0.0.0.0/8", "100.64.0.0/10", "192.0.0.0/24", "198.18.0.0/15"];
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local()
+            || ip.is_broadcast() || ip.is_multicast()
+            || V4_NETWORKS.iter().any(|pattern| matches_ip(pattern, IpAddr::V4(ip))),
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            let embedded = Ipv4Addr::new((segments[6] >> 8) as u8, segments[6] as u8, (segments[7] >> 8) as u8, segments[7] as u8);
+            let mapped = segments[..5].iter().all(|s| *s == 0) && segments[5] == 0xffff;
+            let compatible = segments[..6].iter().all(|s| *s == 0);
+            let nat64 = matches_ip("64:ff9b::/96", IpAddr::V6(ip));
+            ip.is_loopback() || ip.is_unspecified() || ip.is_multicast()
+                || segments[0] & 0xfe00 == 0xfc00
+                || segments[0] & 0xffc0 == 0xfe80
+                || ((mapped || compatible || nat64) && is_private(IpAddr::V4(embedded)))
+        }
+    }
+}
+
+/// Whether `url` lies within the domain at `base`: the scheme, host and port have to be
+/// the same, and the path has to start with the domain's path.
+pub fn within(base: &str, url: &str) -> bool {
+    let (base, uri) = match (base.parse::<Uri>(), url.parse::<Uri>()) {
+        (Ok(base), Ok(uri)) => (base, uri),
+        _ => return false
+    };
+    fn origin(uri: &Uri) -> Option<(String, String, u16)> {
+        let scheme = uri.scheme()?.to_lowercase();
+        // Credentials in the url could make a look-alike authority, e.g. `https://origin@attacker/`.
+        if uri.authority()?.contains('@') {
+            return None;
+        }
+        let port = uri.port().or_else(|| match scheme.as_str() {
+            "http" => Some(80),
+            "https" => Some(443),
+            _ => None
+        })?;
+        Some((scheme, uri.host()?.trim_end_matches('.').to_lowercase(), port))
+    }
+    let dot_segments = uri.path().split('/').any(|segment| segment == ".." || segment == ".");
+    match (origin(&base), origin(&uri)) {
+        (Some(base_origin), Some(origin)) => base_origin == origin && !dot_segments
+            && uri.path().starts_with(base.path()),
+        _ => false
+    }
+}
+
+/// Checks a url against `domains_only` and the host allow and deny lists.
+pub fn check_url(url: &str) -> Result<(), Error> {
+    let cfg = config::<Config>().unwrap();
+    check_url_with(&cfg.security, &cfg.domains, url)
+}
+
+fn check_url_with(security: &SecurityConfig, domains: &HashMap<String, Domain>, url: &str) -> Result<(), Error> {
+    if security.domains_only && !domains.values().any(|domain| within(&domain.url, url)) {
+        return Err(forbidden(format!("Url {} is not within a configured domain.", url)));
+    }
+    let host = url.parse::<Uri>().ok()
+        .and_then(|uri| uri.host().map(str::to_owned))
+        .ok_or_else(|| forbidden(format!("Url {} has no host.", url)))?;
+    if security.deny_hosts.iter().any(|pattern| matches_host(pattern, &host)) {
+        return Err(forbidden(format!("Host {} is not allowed.", host)));
+    }
+    if let Some(ref allow_hosts) = security.allow_hosts {
+        if !allow_hosts.iter().any(|pattern| matches_host(pattern, &host)) {
+            return Err(forbidden(format!("Host {} is not allowed.", host)));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the address a download to `host` connected to. Private addresses can be allowed
+/// explicitly by listing them, or the host name, in `allow_hosts`.
+pub fn check_address(host: &str, ip: IpAddr) -> Result<(), String> {
+    let security = &config::<Config>().unwrap().security;
+    if security.deny_hosts.iter().any(|pattern| matches_ip(pattern, ip)) {
+        return Err(format!("Address {} is not allowed.", ip));
+    }
+    let allowed = security.allow_hosts.as_ref().map_or(false, |hosts| hosts.iter().any(|pattern| {
+        matches_ip(pattern, ip) || (!pattern.starts_with("*.") && matches_host(pattern, host))
+    }));
+    if security.block_private && is_private(ip) && !allowed {
+        return Err(format!("Address {} is private.", ip));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_matching() {
+    assert!(matches_host("*.example.com", "img.example.com"));
+    assert!(!matches_host("*.example.com", "example.com.evil.org"));
+    assert!(matches_host("Example.com", "example.com."));
+    assert!(matches_host("10.0.0.0/8", "10.1.2.3"));
+    assert!(matches_host("::1", "[::1]"));
+    assert!(is_private("192.168.1.1".parse().unwrap()));
+    assert!(is_private("169.254.169.254".parse().unwrap()));
+    assert!(is_private("::ffff:127.0.0.1".parse().unwrap()));
+    assert!(is_private("fd00::1".parse().unwrap()));
+    assert!(!is_private("8.8.8.8".parse().unwrap()));
+    assert!(!is_private("2001:4860::8888".parse().unwrap()));
+    for ip in &["0.1.2.3", "224.0.0.1", "198.18.0.1", "192.0.0.8", "ff02::1", "64:ff9b::7f00:1", "::127.0.0.1"] {
+        assert!(is_private(ip.parse().unwrap()), "{} should be private", ip);
+    }
+    assert!(!is_private("64:ff9b::808:808".parse().unwrap()));
+}
+
+#[test]
+fn test_within() {
+    assert!(within("https://origin.example.com/bucket/", "https://origin.example.com/bucket/a.jpg"));
+    assert!(within("https://origin.example.com/bucket/", "https://ORIGIN.example.com:443/bucket/a.jpg"));
+    assert!(!within("https://origin.example.com/bucket/", "http://origin.example.com/bucket/a.jpg"));
+    assert!(!within("https://origin.example.com/bucket/", "https://origin.example.com:8443/bucket/a.jpg"));
+    assert!(!within("https://origin.example.com/bucket/", "https://origin.example.com/other/a.jpg"));
+    assert!(!within("https://origin.example.com/bucket/", "https://origin.example.com/bucket/../other/a.jpg"));
+    assert!(!within("https://origin.example.com", "https://origin.example.com.attacker.net/a.jpg"));
+    assert!(!within("https://origin.example.com", "https://origin.example.com@attacker.net/a.jpg"));
+}
+
+#[test]
+fn test_check_url() {
+    let mut domains = HashMap::new();
+    domains.insert("img".to_owned(), Domain::new("https://img.example.com/".to_owned()));
+    let mut security = SecurityConfig::default();
+    security.domains_only = true;
+    assert!(check_url_with(&security, &domains, "https://img.example.com/a.jpg").is_ok());
+    assert!(check_url_with(&security, &domains, "https://img.example.com.attacker.net/a.jpg").is_err());
+    assert!(check_url_with(&security, &domains, "https://img.example.com@attacker.net/a.jpg").is_err());
+    assert!(check_url_with(&security, &domains, "https://other.example.com/a.jpg").is_err());
+
+    // Deny lists take precedence over allow lists.
+    security.domains_only = false;
+    security.allow_hosts = Some(vec!["*.example.com".to_owned()]);
+    security.deny_hosts = vec!["secret.example.com".to_owned()];
+    assert!(check_url_with(&security, &domains, "https://other.example.com/a.jpg").is_ok());
+    assert!(check_url_with(&security, &domains, "https://secret.example.com/a.jpg").is_err());
+    assert!(check_url_with(&security, &domains, "https://example.com.attacker.net/a.jpg").is_err());
+}
@@ -0,0 +1,58 @@
+use futures::Future as FutureTrait;
+use imaginator::prelude::*;
+use imaginator::img::{Image, ImageFormat};
+use imaginator::filter::{Args, Future, Context};
+use output::JsonResult;
+
+#[derive(Serialize, Debug)]
+pub struct ImageInfo {
+    pub width: usize,
+    pub height: usize,
+    pub dpi: (f64, f64),
+    pub colorspace: String,
+    pub format: ImageFormat,
+    pub alpha: bool,
+    pub icc_profile: Option<String>,
+    pub pages: usize,
+}
+
+impl ImageInfo {
+    pub fn new(img: &Image) -> Result<Self, Error> {
+        Ok(ImageInfo {
+            width: img.width(),
+            height: img.height(),
+            dpi: img.resolution()?,
+            colorspace: format!("{:?}", img.colorspace()),
+            format: img.format()?,
+            alpha: img.has_alpha(),
+            icc_profile: img.property("icc:description"),
+            pages: img.pages(),
+        })
+    }
+}
+
+pub fn info(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(info, args, 0, context, Image);
+    Box::new(img.and_then(|img| {
+        Ok(Box::new(JsonResult::new(&ImageInfo::new(&img)?)?).into())
+    }))
+}
+
+#[test]
+fn test_serialization() {
+    let info = ImageInfo {
+        width: 640,
+        height: 480,
+        dpi: (72.0, 72.0),
+        colorspace: "sRGB".to_owned(),
+        format: ImageFormat::JPEG,
+        alpha: false,
+        icc_profile: None,
+        pages: 1
+    };
+    let json = JsonResult::new(&info).unwrap().content().unwrap();
+    assert_eq!(
+        String::from_utf8(json.to_vec()).unwrap(),
+        r#"{"width":640,"height":480,"dpi":[72.0,72.0],"colorspace":"sRGB","format":"JPEG","alpha":false,"icc_profile":null,"pages":1}"#
+    );
+}
@@ -5,6 +5,8 @@ extern crate hyper_tls;
 extern crate zip;
 extern crate linked_hash_map;
 extern crate futures;
+extern crate futures_cpupool;
+extern crate tokio_core;
 #[macro_use] extern crate failure;
 extern crate serde;
 #[macro_use] extern crate serde_derive;
@@ -12,10 +14,13 @@ extern crate serde_json;
 extern crate bincode;
 extern crate byteorder;
 extern crate serde_humanize_rs;
+extern crate base64;
+#[macro_use] extern crate lazy_static;
+extern crate chrono;
 
 use futures::{Future as FutureTrait};
 use imaginator::prelude::*;
-use imaginator::img::{CompositeOperator, Colorspace, ColorProfile, CompressionType, Filter as FilterType, ResolutionUnit, ImageFormat, AlphaChannel, Gravity};
+use imaginator::img::{quantum_range, Image, CompositeOperator, Colorspace, ColorProfile, CompressionType, Filter as FilterType, ResolutionUnit, ImageFormat, AlphaChannel, Gravity};
 use imaginator::filter::{Args, Future, exec_from_partial_url, Context};
 use imaginator::cfg::config;
 use std::collections::HashMap;
@@ -26,7 +31,25 @@ pub use cfg::Config;
 pub mod lru_cache;
 pub mod cache;
 
+pub mod connector;
+pub mod guard;
+pub mod s3;
 pub mod download;
+pub mod file;
+pub mod limits;
+pub mod client_hints;
+pub mod output;
+pub mod info;
+pub mod palette;
+pub mod blurhash;
+pub mod thumbhash;
+pub mod placeholder;
+pub mod similarity;
+pub mod stats;
+pub mod deepzoom;
+pub mod mask;
+pub mod frame;
+pub mod generate;
 
 fn init_caches() -> Result<(), Error> {
     let caches = &config::<Config>().unwrap().caches;
@@ -37,6 +60,12 @@ fn init_caches() -> Result<(), Error> {
     Ok(())
 }
 
+fn init() -> Result<(), Error> {
+    limits::init_resource_limits()?;
+    s3::check_credentials()?;
+    init_caches()
+}
+
 fn save_caches() -> Result<(), Error> {
     let caches = &config::<Config>().unwrap().caches;
     for key in caches.keys() {
@@ -49,13 +78,17 @@ fn save_caches() -> Result<(), Error> {
 pub fn plugin() -> PluginInformation {
     let mut map: FilterMap = HashMap::new();
     map.insert("download", &download::filter);
-    map.insert("resize", &resize);
-    map.insert("fit-in", &fit_in);
+    map.insert("file", &file::filter);
+    map.insert("limits", &limits::filter);
+    map.insert("resize", &client_hints::resize);
+    map.insert("fit-in", &client_hints::fit_in);
+    map.insert("fill", &client_hints::fill);
     map.insert("compose", &compose);
     map.insert("trim", &trim);
     map.insert("crop", &crop);
     map.insert("extend", &extend);
     map.insert("format", &format);
+    map.insert("quality", &quality);
     map.insert("pattern", &pattern);
     map.insert("repeat", &repeat);
     map.insert("colorspace", &colorspace);
@@ -65,61 +98,106 @@ pub fn plugin() -> PluginInformation {
     map.insert("canvas", &canvas);
     map.insert("cm", &cm);
     map.insert("sepia", &sepia);
+    map.insert("blur", &blur);
     map.insert("flip", &flip);
     map.insert("flop", &flop);
+    map.insert("rotate", &rotate);
     map.insert("cache", &cache::filter);
     map.insert("dpi", &dpi);
     map.insert("alpha", &alpha);
     map.insert("gravity", &gravity);
     map.insert("bg", &background);
-    PluginInformation::new(map).with_init(&init_caches).with_exit(&save_caches)
+    map.insert("brightness-contrast", &brightness_contrast);
+    map.insert("modulate", &modulate);
+    map.insert("gamma", &gamma);
+    map.insert("levels", &levels);
+    map.insert("auto-level", &auto_level);
+    map.insert("normalize", &normalize);
+    map.insert("grayscale", &grayscale);
+    map.insert("negate", &negate);
+    map.insert("threshold", &threshold);
+    map.insert("round-corners", &mask::round_corners);
+    map.insert("circle", &mask::circle);
+    map.insert("mask", &mask::mask);
+    map.insert("pad", &frame::pad);
+    map.insert("border", &frame::border);
+    map.insert("shadow", &frame::shadow);
+    map.insert("solid", &generate::solid);
+    map.insert("linear-gradient", &generate::linear_gradient);
+    map.insert("radial-gradient", &generate::radial_gradient);
+    map.insert("noise", &generate::noise);
+    map.insert("info", &info::info);
+    map.insert("palette", &palette::filter);
+    map.insert("dominant-color", &palette::dominant_color);
+    map.insert("blurhash", &placeholder::blurhash);
+    map.insert("thumbhash", &placeholder::thumbhash);
+    map.insert("lqip", &placeholder::lqip);
+    map.insert("phash", &similarity::phash_filter);
+    map.insert("dhash", &similarity::dhash_filter);
+    map.insert("compare", &similarity::compare);
+    map.insert("stats", &stats::filter);
+    map.insert("tile", &deepzoom::tile);
+    map.insert("dzi", &deepzoom::dzi);
+    PluginInformation::new(map).with_init(&init).with_exit(&save_caches)
 }
 
-image_filter!(fit_in(img: Image, context: &Context, mut w: isize, mut h: isize) {
+/// Limits a requested size to `max_width` and `max_height` from the config.
+fn clamp_size(w: isize, h: isize) -> (isize, isize) {
     let cfg = &config::<Config>().unwrap().image;
-    if let Some(max_w) = cfg.max_width {
-        if w > max_w {
-            w = max_w;
-        }
-    }
+    clamp_size_to(w, h, cfg.max_width, cfg.max_height)
+}
 
-    if let Some(max_h) = cfg.max_height {
-        if h > max_h {
-            h = max_h;
-        }
-    }
+fn clamp_size_to(w: isize, h: isize, max_width: Option<isize>, max_height: Option<isize>) -> (isize, isize) {
+    (max_width.map_or(w, |max_w| w.min(max_w)), max_height.map_or(h, |max_h| h.min(max_h)))
+}
+
+/// Fills in a zero width or height from the aspect ratio of the image.
+fn complete_size(w: isize, h: isize, img: &Image) -> (isize, isize) {
     let ratio: f64 = (img.width() as f64)/(img.height() as f64);
     if w == 0 {
-        w = ((h as f64) * ratio) as isize;
+        (((h as f64) * ratio) as isize, h)
+    } else if h == 0 {
+        (w, ((w as f64) / ratio) as isize)
+    } else {
+        (w, h)
     }
-    if h == 0 {
-        h = ((w as f64) / ratio) as isize;
-    }
-    img.fit_in(w as usize, h as usize);
+}
+
+image_filter!(fit_in(img: Image, context: &Context, w: isize, h: isize) {
+    let (w, h) = clamp_size(w, h);
+    let (w, h) = complete_size(w, h, &img);
+    img.fit_in(w as usize, h as usize)?;
 });
 
-image_filter!(resize(img: Image, context: &Context, mut w: isize, mut h: isize, filter: Option<FilterType>) {
+image_filter!(resize(img: Image, context: &Context, w: isize, h: isize, filter: Option<FilterType>) {
     let filter = filter.unwrap_or_default();
-    let cfg = &config::<Config>().unwrap().image;
-    if let Some(max_w) = cfg.max_width {
-        if w > max_w {
-            w = max_w;
-        }
-    }
+    let (w, h) = clamp_size(w, h);
+    let (w, h) = complete_size(w, h, &img);
+    img.resize(w as usize, h as usize, &filter)?;
+});
 
-    if let Some(max_h) = cfg.max_height {
-        if h > max_h {
-            h = max_h;
-        }
-    }
-    let ratio: f64 = (img.width() as f64)/(img.height() as f64);
-    if w == 0 {
-        w = ((h as f64) * ratio) as isize;
-    }
-    if h == 0 {
-        h = ((w as f64) / ratio) as isize;
+/// The size to scale a `width` x `height` image to, so that it covers `w` x `h`,
+/// and the offset of the centered `w` x `h` crop, as `(scaled_w, scaled_h, x, y)`.
+fn fill_geometry(width: usize, height: usize, w: isize, h: isize) -> Result<(isize, isize, isize, isize), Error> {
+    if w < 1 || h < 1 {
+        bail!("fill: width and height must be at least 1px.");
     }
-    img.resize(w as usize, h as usize, &filter);
+    let scale = (w as f64 / width as f64).max(h as f64 / height as f64);
+    let scaled_w = ((width as f64 * scale).round() as isize).max(w);
+    let scaled_h = ((height as f64 * scale).round() as isize).max(h);
+    Ok((scaled_w, scaled_h, (scaled_w - w) / 2, (scaled_h - h) / 2))
+}
+
+// fill(img, w, h, filter?) scales the image to cover the whole w x h area, and crops the
+// overflow evenly on both sides. It implements Thumbor's default and imgproxy's `fill` resizing,
+// which `resize` followed by `crop` can't express without knowing the source size.
+image_filter!(fill(img: Image, context: &Context, w: isize, h: isize, filter: Option<FilterType>) {
+    let filter = filter.unwrap_or_default();
+    let (w, h) = clamp_size(w, h);
+    let (scaled_w, scaled_h, x, y) = fill_geometry(img.width(), img.height(), w, h)?;
+    img.resize(scaled_w as usize, scaled_h as usize, &filter)?;
+    img.crop(x, y, w as usize, h as usize)?;
+    img.reset_page()?;
 });
 
 image_filter!(resample(img: Image, context: &Context, x_dpi: f32, y_dpi: f32, filter: Option<FilterType>) {
@@ -145,9 +223,57 @@ image_filter!(resample(img: Image, context: &Context, x_dpi: f32, y_dpi: f32, fi
     img.resample(x_dpi, y_dpi, &filter);
 });
 
-image_filter!(trim(img: Image) {
-    img.trim(15.0)?;
-});
+/// Fuzz used by `trim` when none is given, as a percentage of the quantum range
+/// (roughly the 15 units of a Q16 build that `trim` used to hard-code).
+const DEFAULT_TRIM_FUZZ: f32 = 0.025;
+
+/// trim(img, fuzz?, color?, padding?)
+///
+/// `fuzz` is a percentage of the quantum range. If `color` is given (and isn't `auto`),
+/// only borders of that color are removed. `padding` pixels of that color (or the
+/// background color) are added back afterwards.
+/// The trimmed area (relative to the input image) is reported in the X-Trim-Geometry header.
+pub fn trim(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(trim, args, 0, context, Image);
+    let response_headers = context.response_headers.clone();
+    let args = args.clone();
+    Box::new(img.and_then(move |mut img| {
+        let fuzz = if args.len() > 1 {
+            arg_type!(trim, args, 1, img, f32)
+        } else { DEFAULT_TRIM_FUZZ };
+        let fuzz = fuzz as f64 / 100.0 * quantum_range();
+        let color = if args.len() > 2 {
+            Some(arg_type!(trim, args, 2, img, String)).filter(|color| color != "auto")
+        } else { None };
+        let padding = if args.len() > 3 {
+            arg_type!(trim, args, 3, img, isize)
+        } else { 0 };
+
+        // Trim looks at the corners of the image to find the border color,
+        // so we surround the image with a 1px frame of the requested color.
+        let frame = if let Some(ref color) = color {
+            frame::pad_image(&mut img, 1, 1, 1, 1, color)?;
+            1
+        } else { 0 };
+        img.trim(fuzz)?;
+        let (_, _, x, y) = img.page()?;
+        response_headers.borrow_mut().insert("X-Trim-Geometry".to_owned(),
+            format!("{}x{}+{}+{}", img.width(), img.height(), x - frame, y - frame)
+        );
+        img.reset_page()?;
+
+        if padding > 0 {
+            match color {
+                Some(ref color) => frame::pad_image(&mut img, padding, padding, padding, padding, color)?,
+                None => {
+                    let (w, h) = (img.width(), img.height());
+                    img.extend(-padding, -padding, w + 2 * padding as usize, h + 2 * padding as usize)?;
+                }
+            }
+        }
+        Ok(img.into())
+    }))
+}
 
 image_filter!(crop(img: Image, x: isize, y: isize, w: isize, h: isize) {
     img.crop(x, y, w as usize, h as usize)?;
@@ -163,6 +289,13 @@ image_filter!(format(img: Image, format: ImageFormat) {
     img.set_format(&format)?;
 });
 
+// quality(img, quality) sets the compression quality (1-100) of formats like JPEG and WebP,
+// for the Thumbor and imgproxy `quality` options and for native urls alike.
+image_filter!(quality(img: Image, quality: isize) {
+    let quality = check_range("quality", "quality", quality as f32, 1.0, 100.0)?;
+    img.set_quality(quality as usize)?;
+});
+
 pub fn compose(context: &mut Context, args: &Args) -> Box<Future> {
     let dst = arg_type!(compose, args, 0, context, Image);
     let src = arg_type!(compose, args, 1, context, Image);
@@ -204,6 +337,27 @@ image_filter!(flop(img: Image) {
     img.flop()?;
 });
 
+/// Normalizes a clockwise rotation to `0.0..360.0` degrees, or `None` if it is a no-op.
+fn rotation(degrees: f32) -> Result<Option<f64>, Error> {
+    if !degrees.is_finite() {
+        bail!("rotate: invalid angle: {}", degrees);
+    }
+    let degrees = degrees as f64 % 360.0;
+    let degrees = if degrees < 0.0 { degrees + 360.0 } else { degrees };
+    Ok(if degrees == 0.0 { None } else { Some(degrees) })
+}
+
+// rotate(img, degrees, background?) rotates clockwise, filling the uncovered corners with
+// `background` (transparent by default). The IIIF, Thumbor and imgproxy front-ends map their
+// rotation parameters to it, and it's just as useful in native urls.
+image_filter!(rotate(img: Image, degrees: f32, background: Option<String>) {
+    if let Some(degrees) = rotation(degrees)? {
+        let background = background.unwrap_or_else(|| "none".to_owned());
+        img.rotate(degrees, &background)?;
+        img.reset_page()?;
+    }
+});
+
 image_filter!(cm(img: Image, x: f32, y: f32) {
     let x = x * 0.3937008;
     let y = y * 0.3937008;
@@ -223,6 +377,95 @@ image_filter!(sepia(img: Image, threshold: f32) {
     img.sepia(threshold as f64)?;
 });
 
+/// Blurring takes time proportional to the sigma, so it is capped like Thumbor caps
+/// the radius of its `blur` filter.
+const MAX_BLUR_SIGMA: f32 = 150.0;
+
+// blur(img, sigma) applies a gaussian blur, for the Thumbor and imgproxy `blur` options
+// and for native urls alike.
+image_filter!(blur(img: Image, sigma: f32) {
+    let sigma = check_range("blur", "sigma", sigma, 0.0, MAX_BLUR_SIGMA)?;
+    if sigma > 0.0 {
+        img.blur(sigma)?;
+    }
+});
+
+/// Checks that the `name` argument of `filter` lies within `min..=max`.
+fn check_range(filter: &str, name: &str, value: f32, min: f32, max: f32) -> Result<f64, Error> {
+    if !(min..=max).contains(&value) {
+        bail!("{}: {} must be between {} and {}, got {}.", filter, name, min, max, value);
+    }
+    Ok(value as f64)
+}
+
+fn check_gamma(filter: &str, gamma: f32) -> Result<(), Error> {
+    if gamma.is_nan() || gamma <= 0.0 {
+        bail!("{}: gamma must be greater than 0.", filter);
+    }
+    Ok(())
+}
+
+/// Black and white points given in percent, like in `convert -level`, as fractions of the
+/// quantum range.
+fn level_points(black: f32, white: f32) -> Result<(f64, f64), Error> {
+    let black = check_range("levels", "black point", black, 0.0, 100.0)?;
+    let white = check_range("levels", "white point", white, 0.0, 100.0)?;
+    if black >= white {
+        bail!("levels: black point must be lower than white point.");
+    }
+    Ok((black / 100.0, white / 100.0))
+}
+
+// Brightness and contrast are percentages of change, like in `convert -brightness-contrast`.
+image_filter!(brightness_contrast(img: Image, brightness: f32, contrast: f32) {
+    let brightness = check_range("brightness-contrast", "brightness", brightness, -100.0, 100.0)?;
+    let contrast = check_range("brightness-contrast", "contrast", contrast, -100.0, 100.0)?;
+    img.brightness_contrast(brightness, contrast)?;
+});
+
+// All values are percentages, 100 being no change. A hue of 0 or 200 rotates it by 180 degrees.
+image_filter!(modulate(img: Image, brightness: f32, saturation: f32, hue: f32) {
+    if brightness < 0.0 || saturation < 0.0 {
+        bail!("modulate: brightness and saturation cannot be negative.");
+    }
+    let hue = check_range("modulate", "hue", hue, 0.0, 200.0)?;
+    img.modulate(brightness as f64, saturation as f64, hue)?;
+});
+
+image_filter!(gamma(img: Image, gamma: f32) {
+    check_gamma("gamma", gamma)?;
+    img.gamma(gamma as f64)?;
+});
+
+image_filter!(levels(img: Image, black: f32, white: f32, gamma: Option<f32>) {
+    let (black, white) = level_points(black, white)?;
+    let gamma = gamma.unwrap_or(1.0);
+    check_gamma("levels", gamma)?;
+    img.levels(black, white, gamma as f64)?;
+});
+
+image_filter!(auto_level(img: Image) {
+    img.auto_level()?;
+});
+
+image_filter!(normalize(img: Image) {
+    img.normalize()?;
+});
+
+image_filter!(grayscale(img: Image) {
+    img.set_colorspace(&Colorspace::GRAY)?;
+});
+
+image_filter!(negate(img: Image) {
+    img.negate()?;
+});
+
+// The threshold is given in percent of the quantum range.
+image_filter!(threshold(img: Image, threshold: f32) {
+    let threshold = check_range("threshold", "threshold", threshold, 0.0, 100.0)?;
+    img.threshold(threshold / 100.0)?;
+});
+
 image_filter!(gravity(img: Image, gravity: Gravity) {
     img.set_gravity(&gravity)?;
 });
@@ -289,3 +532,57 @@ image_filter!(canvas(img: Image, border_x: f32, border_y: f32) {
 image_filter!(background(img: Image, color: String) {
     img.set_background_color(&color)?;
 });
+
+#[test]
+fn test_check_range() {
+    assert_eq!(check_range("threshold", "threshold", 50.0, 0.0, 100.0).unwrap(), 50.0);
+    assert_eq!(check_range("threshold", "threshold", 100.0, 0.0, 100.0).unwrap(), 100.0);
+    assert!(check_range("threshold", "threshold", -1.0, 0.0, 100.0).is_err());
+    assert!(check_range("threshold", "threshold", 101.0, 0.0, 100.0).is_err());
+    assert!(check_range("threshold", "threshold", ::std::f32::NAN, 0.0, 100.0).is_err());
+}
+
+#[test]
+fn test_level_points() {
+    assert_eq!(level_points(10.0, 90.0).unwrap(), (0.1, 0.9));
+    assert_eq!(level_points(0.0, 100.0).unwrap(), (0.0, 1.0));
+    assert!(level_points(90.0, 10.0).is_err());
+    assert!(level_points(50.0, 50.0).is_err());
+    assert!(level_points(-5.0, 50.0).is_err());
+    assert!(level_points(0.0, 150.0).is_err());
+}
+
+#[test]
+fn test_check_gamma() {
+    assert!(check_gamma("gamma", 2.2).is_ok());
+    assert!(check_gamma("gamma", 0.0).is_err());
+    assert!(check_gamma("gamma", -1.0).is_err());
+}
+
+#[test]
+fn test_rotation() {
+    assert_eq!(rotation(90.0).unwrap(), Some(90.0));
+    assert_eq!(rotation(-90.0).unwrap(), Some(270.0));
+    assert_eq!(rotation(450.0).unwrap(), Some(90.0));
+    assert_eq!(rotation(0.0).unwrap(), None);
+    assert_eq!(rotation(-720.0).unwrap(), None);
+    assert!(rotation(::std::f32::NAN).is_err());
+    assert!(rotation(::std::f32::INFINITY).is_err());
+}
+
+#[test]
+fn test_clamp_size_to() {
+    assert_eq!(clamp_size_to(4000, 3000, None, None), (4000, 3000));
+    assert_eq!(clamp_size_to(4000, 3000, Some(2000), Some(2000)), (2000, 2000));
+    assert_eq!(clamp_size_to(100, 0, Some(2000), Some(2000)), (100, 0));
+}
+
+#[test]
+fn test_fill_geometry() {
+    // A landscape image is scaled to the requested height, and cropped at the sides.
+    assert_eq!(fill_geometry(400, 200, 100, 100).unwrap(), (200, 100, 50, 0));
+    // A portrait image is scaled to the requested width, and cropped at the top and bottom.
+    assert_eq!(fill_geometry(200, 400, 100, 50).unwrap(), (100, 200, 0, 75));
+    assert_eq!(fill_geometry(300, 200, 600, 400).unwrap(), (600, 400, 0, 0));
+    assert!(fill_geometry(300, 200, 0, 100).is_err());
+}
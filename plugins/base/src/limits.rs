@@ -0,0 +1,78 @@
+//! Limits on source images, checked before they're decoded.
+use hyper::StatusCode;
+use futures::{future, Future as FutureTrait};
+use imaginator::img::{self, Image, ResourceType};
+use imaginator::filter::{Args, Context, FilterArg, Future, error_response, exec_filter};
+use imaginator::prelude::*;
+use imaginator::cfg::config;
+use ::Config;
+use cfg::SourceLimits;
+
+/// A limit violation, returned to the client as 413 Payload Too Large.
+pub fn too_large(message: String) -> Error {
+    error_response(StatusCode::PayloadTooLarge, message)
+}
+
+/// Checks a source image against `limits`, and against ImageMagick's width and height limits,
+/// which would otherwise only fail while decoding.
+/// Dimensions are read with a ping, so oversized images are never decoded.
+pub fn check_source_with(buffer: &Vec<u8>, resolution: Option<f64>, limits: &SourceLimits) -> Result<(), Error> {
+    if let Some(max_size) = limits.max_source_size {
+        if buffer.len() > max_size {
+            return Err(too_large(format!("Source image is {} bytes, exceeding the limit of {} bytes.", buffer.len(), max_size)));
+        }
+    }
+    let mut image = Image::new(None, resolution)?;
+    // Unreadable images are left for the decoder to report.
+    if image.ping(buffer).is_err() {
+        return Ok(());
+    }
+    let (width, height, pages) = (image.width(), image.height(), image.pages().max(1));
+    if width as u64 > img::resource_limit(ResourceType::Width) || height as u64 > img::resource_limit(ResourceType::Height) {
+        return Err(too_large(format!("Source image is {}x{} pixels, exceeding the width or height limit.", width, height)));
+    }
+    if let Some(max_pixels) = limits.max_source_pixels {
+        let pixels = width.checked_mul(height).and_then(|pixels| pixels.checked_mul(pages));
+        if pixels.map_or(true, |pixels| pixels > max_pixels) {
+            return Err(too_large(format!("Source image is {}x{} pixels ({} frames), exceeding the limit of {} pixels.",
+                width, height, pages, max_pixels)));
+        }
+    }
+    Ok(())
+}
+
+/// Checks a source image against the configured `max_source_size` and `max_source_pixels`.
+pub fn check_source(buffer: &Vec<u8>, resolution: Option<f64>) -> Result<(), Error> {
+    check_source_with(buffer, resolution, &config::<Config>().unwrap().image.source_limits)
+}
+
+/// Sets the configured ImageMagick resource limits.
+pub fn init_resource_limits() -> Result<(), Error> {
+    for (resource, limit) in config::<Config>().unwrap().image.resource_limits.limits() {
+        img::set_resource_limit(resource, limit)?;
+    }
+    Ok(())
+}
+
+/// `limits(img, profile)` decodes `img` checking it against a profile from `source_profiles`
+/// instead of the default source limits, e.g. to allow bigger sources in print presets.
+///
+/// This is the only per-alias override: ImageMagick's own memory, disk, time and other
+/// resource limits are process-wide, so they can't be changed per request and always apply.
+pub fn filter(context: &mut Context, args: &Args) -> Box<Future> {
+    let source = match args.get(0) {
+        Some(&FilterArg::Img(ref source)) => source.clone(),
+        _ => return Box::new(future::err(format_err!("Argument 1 to `limits` must be an image")))
+    };
+    let profile = arg_type!(limits, args, 1, String);
+    let profile = match config::<Config>().unwrap().image.source_profiles.get(&profile) {
+        Some(profile) => profile,
+        None => return Box::new(future::err(format_err!("No such source profile: {}", profile)))
+    };
+    Box::new(exec_filter(context, &source).and_then(move |result| {
+        let resolution = result.dpi().ok().map(|dpi| dpi.0);
+        let buffer = result.content()?;
+        check_source_with(&*buffer, resolution, profile)?;
+        Ok(Box::new(Image::new(&*buffer, resolution)?).into())
+    }))
+}
@@ -0,0 +1,125 @@
+use std::str::FromStr;
+use futures::Future as FutureTrait;
+use imaginator::prelude::*;
+use imaginator::img::{Image, ImageFormat, AlphaChannel, CompositeOperator, Filter as FilterType};
+use imaginator::filter::{Args, Future, Context};
+
+#[derive(PartialEq,Eq,Debug,Fail)]
+#[fail(display = "Unknown mask channel: {}", _0)]
+pub struct UnknownMaskChannel(String);
+
+/// Which part of the mask image decides about the opacity of the result.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MaskChannel {
+    Alpha,
+    Luminance,
+}
+
+impl FromStr for MaskChannel {
+    type Err = UnknownMaskChannel;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let lowercase = input.to_owned().to_lowercase();
+        Ok(match lowercase.as_str() {
+            "alpha" => MaskChannel::Alpha,
+            "luminance" => MaskChannel::Luminance,
+            _ => return Err(UnknownMaskChannel(input.to_owned()))
+        })
+    }
+}
+
+impl ::imaginator::filter::ArgTypeImg for MaskChannel {}
+
+/// Renders a white shape on a black background, to be used as a luminance mask.
+fn shape_mask(width: usize, height: usize, shape: &str) -> Result<Image, Error> {
+    let svg = format!(
+        "<?xml version=\"1.0\"?><svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\">\
+         <rect width=\"{w}\" height=\"{h}\" fill=\"black\"/>{}</svg>",
+        shape, w=width, h=height
+    );
+    Image::new(&svg.into_bytes(), None)
+}
+
+/// Multiplies the alpha channel of `img` by the mask.
+fn apply_mask(img: &mut Image, mut mask: Image, channel: MaskChannel) -> Result<(), Error> {
+    if mask.width() != img.width() || mask.height() != img.height() {
+        mask.resize(img.width(), img.height(), &FilterType::default())?;
+    }
+    if channel == MaskChannel::Luminance {
+        mask.set_alpha_channel(&AlphaChannel::Off)?;
+        mask.set_alpha_channel(&AlphaChannel::Copy)?;
+    }
+    img.set_alpha_channel(&AlphaChannel::Set)?;
+    img.compose(&CompositeOperator::DstIn, &mask, 0, 0)?;
+    ensure_alpha_format(img)
+}
+
+/// Switches to PNG if the current format of the image can't store transparency.
+pub fn ensure_alpha_format(img: &mut Image) -> Result<(), Error> {
+    if !img.format()?.supports_alpha() {
+        img.set_format(&ImageFormat::PNG)?;
+    }
+    Ok(())
+}
+
+/// The largest square centered within a `width` x `height` image, as `(x, y, size)`.
+fn center_square(width: usize, height: usize) -> (isize, isize, usize) {
+    let size = width.min(height);
+    (((width - size) / 2) as isize, ((height - size) / 2) as isize, size)
+}
+
+image_filter!(round_corners(img: Image, radius: isize) {
+    if radius < 0 {
+        bail!("round-corners: radius cannot be negative.");
+    }
+    let shape = format!(
+        "<rect width=\"{}\" height=\"{}\" rx=\"{r}\" ry=\"{r}\" fill=\"white\"/>",
+        img.width(), img.height(), r=radius
+    );
+    let mask = shape_mask(img.width(), img.height(), &shape)?;
+    apply_mask(&mut img, mask, MaskChannel::Luminance)?;
+});
+
+// Non-square images are cropped to a centered square first, so that the result is
+// always a circle rather than an ellipse.
+image_filter!(circle(img: Image) {
+    let (x, y, size) = center_square(img.width(), img.height());
+    if size != img.width() || size != img.height() {
+        img.crop(x, y, size, size)?;
+        img.reset_page()?;
+    }
+    let shape = format!(
+        "<circle cx=\"{r}\" cy=\"{r}\" r=\"{r}\" fill=\"white\"/>",
+        r=size as f64 / 2.0
+    );
+    let mask = shape_mask(size, size, &shape)?;
+    apply_mask(&mut img, mask, MaskChannel::Luminance)?;
+});
+
+pub fn mask(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(mask, args, 0, context, Image);
+    let mask = arg_type!(mask, args, 1, context, Image);
+    let args = args.clone();
+    Box::new(img.join(mask).and_then(move |(mut img, mask)| {
+        let channel = if args.len() > 2 {
+            arg_type!(mask, args, 2, img, MaskChannel)
+        } else {
+            MaskChannel::Luminance
+        };
+        apply_mask(&mut img, *mask, channel)?;
+        Ok(img.into())
+    }))
+}
+
+#[test]
+fn test_mask_channel() {
+    assert_eq!("alpha".parse::<MaskChannel>(), Ok(MaskChannel::Alpha));
+    assert_eq!("Luminance".parse::<MaskChannel>(), Ok(MaskChannel::Luminance));
+    assert_eq!("red".parse::<MaskChannel>(), Err(UnknownMaskChannel("red".to_owned())));
+}
+
+#[test]
+fn test_center_square() {
+    assert_eq!(center_square(300, 200), (50, 0, 200));
+    assert_eq!(center_square(200, 301), (0, 50, 200));
+    assert_eq!(center_square(100, 100), (0, 0, 100));
+}
@@ -0,0 +1,58 @@
+use std::rc::Rc;
+use serde::Serialize;
+use serde_json;
+use imaginator::prelude::*;
+
+/// A non-image filter result, serialized as JSON.
+#[derive(Debug, Clone)]
+pub struct JsonResult {
+    body: Rc<Vec<u8>>
+}
+
+impl JsonResult {
+    pub fn new<T: Serialize>(value: &T) -> Result<Self, Error> {
+        Ok(JsonResult {
+            body: Rc::new(serde_json::to_vec(value)?)
+        })
+    }
+}
+
+impl FilterResult for JsonResult {
+    fn content_type(&self) -> Result<ContentType, Error> {
+        Ok(ContentType::json())
+    }
+
+    fn content(&self) -> Result<Rc<Vec<u8>>, Error> {
+        Ok(self.body.clone())
+    }
+}
+
+/// A non-image filter result consisting of a single string.
+#[derive(Debug, Clone)]
+pub struct TextResult {
+    content_type: ContentType,
+    body: Rc<Vec<u8>>
+}
+
+impl TextResult {
+    pub fn new(text: String) -> Self {
+        TextResult::with_content_type(text, ContentType::plaintext())
+    }
+
+    pub fn with_content_type(text: String, content_type: ContentType) -> Self {
+        TextResult {
+            content_type,
+            body: Rc::new(text.into_bytes())
+        }
+    }
+}
+
+impl FilterResult for TextResult {
+    fn content_type(&self) -> Result<ContentType, Error> {
+        Ok(self.content_type.clone())
+    }
+
+    fn content(&self) -> Result<Rc<Vec<u8>>, Error> {
+        Ok(self.body.clone())
+    }
+}
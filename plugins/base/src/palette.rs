@@ -0,0 +1,77 @@
+use futures::Future as FutureTrait;
+use imaginator::prelude::*;
+use imaginator::img::{Image, Colorspace};
+use imaginator::filter::{Args, Future, Context};
+use output::JsonResult;
+
+/// Quantization cost grows with the number of pixels, and a thumbnail is enough
+/// to find the dominant colors.
+const MAX_PALETTE_SOURCE_SIZE: usize = 256;
+/// How many colors `dominant-color` picks the most common one from.
+const DOMINANT_COLOR_CANDIDATES: usize = 8;
+
+#[derive(Serialize, Debug)]
+pub struct PaletteColor {
+    pub color: String,
+    /// Fraction of the image covered by this color.
+    pub coverage: f64,
+}
+
+/// Returns up to `colors` dominant colors of the image, the most common first.
+pub fn palette(img: &Image, colors: usize) -> Result<Vec<PaletteColor>, Error> {
+    let mut img = img.clone();
+    if img.width() > MAX_PALETTE_SOURCE_SIZE || img.height() > MAX_PALETTE_SOURCE_SIZE {
+        img.fit_in(MAX_PALETTE_SOURCE_SIZE, MAX_PALETTE_SOURCE_SIZE)?;
+    }
+    if img.colorspace() != Colorspace::sRGB {
+        img.set_colorspace(&Colorspace::sRGB)?;
+    }
+    img.quantize(colors)?;
+    Ok(rank(img.histogram()?, colors))
+}
+
+/// Turns a histogram of `(color, pixel count)` into up to `colors` palette entries,
+/// the most common first. Colors with equal counts keep their order.
+fn rank(mut histogram: Vec<(String, usize)>, colors: usize) -> Vec<PaletteColor> {
+    histogram.sort_by(|a, b| b.1.cmp(&a.1));
+    let total: usize = histogram.iter().map(|&(_, count)| count).sum();
+    histogram.into_iter().take(colors).map(|(color, count)| PaletteColor {
+        color,
+        coverage: count as f64 / total as f64
+    }).collect()
+}
+
+pub fn filter(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(palette, args, 0, context, Image);
+    let args = args.clone();
+    Box::new(img.and_then(move |img| {
+        let colors = arg_type!(palette, args, 1, img, isize);
+        if colors < 1 {
+            bail!("palette: the number of colors must be at least 1.");
+        }
+        Ok(Box::new(JsonResult::new(&palette(&img, colors as usize)?)?).into())
+    }))
+}
+
+pub fn dominant_color(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(dominant_color, args, 0, context, Image);
+    Box::new(img.and_then(|img| {
+        let color = palette(&img, DOMINANT_COLOR_CANDIDATES)?.into_iter().next()
+            .ok_or_else(|| format_err!("dominant-color: the image has no pixels."))?;
+        Ok(Box::new(JsonResult::new(&color)?).into())
+    }))
+}
+
+#[test]
+fn test_rank() {
+    let histogram = vec![
+        ("#000000".to_owned(), 10),
+        ("#FF0000".to_owned(), 50),
+        ("#00FF00".to_owned(), 10),
+        ("#0000FF".to_owned(), 30),
+    ];
+    let palette = rank(histogram, 3);
+    let colors: Vec<_> = palette.iter().map(|entry| (entry.color.as_str(), entry.coverage)).collect();
+    assert_eq!(colors, vec![("#FF0000", 0.5), ("#0000FF", 0.3), ("#000000", 0.1)]);
+    assert!(rank(vec![], 3).is_empty());
+}
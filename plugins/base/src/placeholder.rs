@@ -0,0 +1,68 @@
+use futures::Future as FutureTrait;
+use base64;
+use imaginator::prelude::*;
+use imaginator::img::{Image, ImageFormat, Colorspace, Filter as FilterType};
+use imaginator::filter::{Args, Future, Context};
+use output::TextResult;
+use blurhash;
+use thumbhash;
+
+/// BlurHash only captures low frequencies, so there's no point in looking at more pixels.
+const BLURHASH_SOURCE_SIZE: usize = 64;
+const LQIP_QUALITY: usize = 30;
+
+/// Returns a downscaled sRGB copy of the image, so that hashing is fast.
+fn thumbnail(img: &Image, size: usize) -> Result<Image, Error> {
+    let mut img = img.clone();
+    if img.width() > size || img.height() > size {
+        img.fit_in(size, size)?;
+    }
+    if img.colorspace() != Colorspace::sRGB {
+        img.set_colorspace(&Colorspace::sRGB)?;
+    }
+    Ok(img)
+}
+
+pub fn blurhash(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(blurhash, args, 0, context, Image);
+    let args = args.clone();
+    Box::new(img.and_then(move |img| {
+        let x = arg_type!(blurhash, args, 1, img, isize);
+        let y = arg_type!(blurhash, args, 2, img, isize);
+        if x < 1 || x > 9 || y < 1 || y > 9 {
+            bail!("blurhash: the number of components must be between 1 and 9.");
+        }
+        let img = thumbnail(&img, BLURHASH_SOURCE_SIZE)?;
+        let hash = blurhash::encode(x as u32, y as u32, img.width(), img.height(), &img.pixels("RGB")?);
+        Ok(Box::new(TextResult::new(hash)).into())
+    }))
+}
+
+pub fn thumbhash(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(thumbhash, args, 0, context, Image);
+    Box::new(img.and_then(|img| {
+        let img = thumbnail(&img, thumbhash::MAX_SIZE)?;
+        let hash = thumbhash::encode(img.width(), img.height(), &img.pixels("RGBA")?);
+        Ok(Box::new(TextResult::new(base64::encode(&hash))).into())
+    }))
+}
+
+// The image is returned as a data URI by `lqip`, so that it can be inlined in HTML.
+image_filter!(lqip_image(img: Image, width: isize) {
+    if width < 1 {
+        bail!("lqip: width must be at least 1px.");
+    }
+    let height = ((img.height() * width as usize) as f64 / img.width() as f64).round().max(1.0);
+    img.resize(width as usize, height as usize, &FilterType::default())?;
+    let format = if img.has_alpha() { ImageFormat::PNG } else { ImageFormat::JPEG };
+    img.set_format(&format)?;
+    img.set_quality(LQIP_QUALITY)?;
+});
+
+pub fn lqip(context: &mut Context, args: &Args) -> Box<Future> {
+    Box::new(lqip_image(context, args).and_then(|result| {
+        let content_type = result.content_type()?;
+        let uri = format!("data:{};base64,{}", content_type.0, base64::encode(&*result.content()?));
+        Ok(Box::new(TextResult::new(uri)).into())
+    }))
+}
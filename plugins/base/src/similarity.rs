@@ -0,0 +1,112 @@
+use futures::Future as FutureTrait;
+use std::f64::consts::PI;
+use imaginator::prelude::*;
+use imaginator::img::{Image, Colorspace, Metric, Filter as FilterType};
+use imaginator::filter::{Args, Future, Context};
+use output::{TextResult, JsonResult};
+
+const HASH_SIZE: usize = 8;
+/// pHash looks at the lowest frequencies of a DCT computed on a bigger thumbnail.
+const PHASH_SOURCE_SIZE: usize = HASH_SIZE * 4;
+
+/// Returns the intensity of every pixel of the image scaled to `width`x`height`.
+fn gray_thumbnail(img: &Image, width: usize, height: usize) -> Result<Vec<u8>, Error> {
+    let mut img = img.clone();
+    img.set_colorspace(&Colorspace::GRAY)?;
+    img.resize(width, height, &FilterType::default())?;
+    img.pixels("I")
+}
+
+/// Difference hash of a (HASH_SIZE + 1)xHASH_SIZE grayscale image:
+/// each bit tells whether a pixel is brighter than its right neighbour.
+pub fn dhash(pixels: &[u8]) -> u64 {
+    let mut hash = 0;
+    for y in 0..HASH_SIZE {
+        for x in 0..HASH_SIZE {
+            let left = pixels[y * (HASH_SIZE + 1) + x];
+            let right = pixels[y * (HASH_SIZE + 1) + x + 1];
+            hash = (hash << 1) | (left > right) as u64;
+        }
+    }
+    hash
+}
+
+/// Perceptual hash of a PHASH_SOURCE_SIZE square grayscale image:
+/// each bit tells whether a low-frequency DCT coefficient is above their median.
+pub fn phash(pixels: &[u8]) -> u64 {
+    let n = PHASH_SOURCE_SIZE;
+    let mut coefficients = Vec::with_capacity(HASH_SIZE * HASH_SIZE);
+    for v in 0..HASH_SIZE {
+        for u in 0..HASH_SIZE {
+            let mut sum = 0.0;
+            for y in 0..n {
+                for x in 0..n {
+                    sum += pixels[y * n + x] as f64
+                        * (PI * u as f64 * (2 * x + 1) as f64 / (2 * n) as f64).cos()
+                        * (PI * v as f64 * (2 * y + 1) as f64 / (2 * n) as f64).cos();
+                }
+            }
+            coefficients.push(sum);
+        }
+    }
+    let mut sorted = coefficients.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = (sorted[sorted.len() / 2 - 1] + sorted[sorted.len() / 2]) / 2.0;
+    coefficients.iter().fold(0, |hash, &c| (hash << 1) | (c > median) as u64)
+}
+
+pub fn phash_filter(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(phash, args, 0, context, Image);
+    Box::new(img.and_then(|img| {
+        let hash = phash(&gray_thumbnail(&img, PHASH_SOURCE_SIZE, PHASH_SOURCE_SIZE)?);
+        Ok(Box::new(TextResult::new(format!("{:016x}", hash))).into())
+    }))
+}
+
+pub fn dhash_filter(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(dhash, args, 0, context, Image);
+    Box::new(img.and_then(|img| {
+        let hash = dhash(&gray_thumbnail(&img, HASH_SIZE + 1, HASH_SIZE)?);
+        Ok(Box::new(TextResult::new(format!("{:016x}", hash))).into())
+    }))
+}
+
+#[derive(Serialize, Debug)]
+struct Comparison {
+    metric: String,
+    distortion: f64,
+}
+
+/// compare(img_a, img_b, metric)
+///
+/// If the images differ in size, the second one is scaled to the size of the first.
+pub fn compare(context: &mut Context, args: &Args) -> Box<Future> {
+    let a = arg_type!(compare, args, 0, context, Image);
+    let b = arg_type!(compare, args, 1, context, Image);
+    let args = args.clone();
+    Box::new(a.join(b).and_then(move |(a, b)| {
+        let metric = arg_type!(compare, args, 2, a, Metric);
+        if a.width() != b.width() || a.height() != b.height() {
+            b.resize(a.width(), a.height(), &FilterType::default())?;
+        }
+        let comparison = Comparison {
+            metric: format!("{:?}", metric),
+            distortion: a.compare(&b, &metric)
+        };
+        Ok(Box::new(JsonResult::new(&comparison)?).into())
+    }))
+}
+
+#[test]
+fn test_dhash_gradient() {
+    // Every row gets darker to the right, so every bit is set.
+    let pixels: Vec<u8> = (0..HASH_SIZE).flat_map(|_| (0..HASH_SIZE as u8 + 1).rev()).collect();
+    assert_eq!(dhash(&pixels), u64::max_value());
+}
+
+#[test]
+fn test_phash_is_stable_under_brightness_change() {
+    let pixels: Vec<u8> = (0..PHASH_SOURCE_SIZE * PHASH_SOURCE_SIZE).map(|i| ((i * 37) % 200) as u8).collect();
+    let brighter: Vec<u8> = pixels.iter().map(|p| p + 50).collect();
+    assert_eq!(phash(&pixels), phash(&brighter));
+}
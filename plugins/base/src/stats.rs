@@ -0,0 +1,169 @@
+use futures::Future as FutureTrait;
+use imaginator::prelude::*;
+use imaginator::img::{Image, Colorspace};
+use imaginator::filter::{Args, Future, Context};
+use output::JsonResult;
+
+/// Statistics are computed from a sample of at most this many pixels in each dimension,
+/// so that huge prepress files don't have to be exported as a whole.
+const MAX_STATS_SOURCE_SIZE: usize = 1024;
+const MAX_LEVEL: f64 = 65535.0;
+
+#[derive(Serialize, Debug)]
+pub struct ChannelStats {
+    pub channel: &'static str,
+    /// min, max, mean and stddev are fractions of the full channel range.
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub stddev: f64,
+    /// Number of pixels for each of the 256 levels of the channel.
+    pub histogram: Vec<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AreaCoverage {
+    /// Both values are in percent, so they range from 0 to 400.
+    pub max: f64,
+    pub mean: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImageStats {
+    pub colorspace: String,
+    /// Size of the sample the statistics were computed from.
+    pub sample: (usize, usize),
+    pub channels: Vec<ChannelStats>,
+    /// Only reported for CMYK images.
+    pub total_area_coverage: Option<AreaCoverage>,
+}
+
+/// The size to sample a `width` x `height` image at, keeping its aspect ratio,
+/// or `None` if it is small enough already.
+fn sample_size(width: usize, height: usize, max: usize) -> Option<(usize, usize)> {
+    if width <= max && height <= max {
+        return None;
+    }
+    let scale = max as f64 / width.max(height) as f64;
+    Some((((width as f64 * scale).round() as usize).max(1), ((height as f64 * scale).round() as usize).max(1)))
+}
+
+/// Computes statistics of the `index`-th channel out of `count` interleaved 16-bit channels.
+pub fn channel_stats(channel: &'static str, pixels: &[u16], index: usize, count: usize) -> ChannelStats {
+    let mut histogram = vec![0; 256];
+    let (mut min, mut max, mut sum) = (u16::max_value(), 0, 0.0);
+    for pixel in pixels.chunks(count) {
+        let value = pixel[index];
+        histogram[(value >> 8) as usize] += 1;
+        min = min.min(value);
+        max = max.max(value);
+        sum += value as f64;
+    }
+    let total = (pixels.len() / count) as f64;
+    let mean = sum / total;
+    let variance = pixels.chunks(count).map(|pixel| (pixel[index] as f64 - mean).powi(2)).sum::<f64>() / total;
+    ChannelStats {
+        channel,
+        min: min as f64 / MAX_LEVEL,
+        max: max as f64 / MAX_LEVEL,
+        mean: mean / MAX_LEVEL,
+        stddev: variance.sqrt() / MAX_LEVEL,
+        histogram
+    }
+}
+
+/// Computes the total ink coverage of CMYK pixels, with `count` interleaved 16-bit channels
+/// starting with C, M, Y and K.
+pub fn total_area_coverage(pixels: &[u16], count: usize) -> AreaCoverage {
+    let mut max = 0.0f64;
+    let mut sum = 0.0;
+    let mut total = 0;
+    for pixel in pixels.chunks(count) {
+        let coverage = pixel[..4].iter().map(|&v| v as f64 / MAX_LEVEL * 100.0).sum::<f64>();
+        max = max.max(coverage);
+        sum += coverage;
+        total += 1;
+    }
+    AreaCoverage { max, mean: sum / total as f64 }
+}
+
+/// Computes statistics of a sample of the image. The sample consists of original pixels
+/// rather than averages, so that clipped pixels and ink coverage aren't smoothed out,
+/// although details smaller than the sampling step can be missed.
+pub fn stats(img: &Image) -> Result<ImageStats, Error> {
+    let mut img = img.clone();
+    if let Some((w, h)) = sample_size(img.width(), img.height(), MAX_STATS_SOURCE_SIZE) {
+        img.sample(w, h)?;
+    }
+    let colorspace = img.colorspace();
+    let mut channels: Vec<(&'static str, char)> = match colorspace {
+        Colorspace::CMYK => vec![("cyan", 'C'), ("magenta", 'M'), ("yellow", 'Y'), ("black", 'K')],
+        Colorspace::GRAY | Colorspace::LinearGRAY => vec![("gray", 'I')],
+        Colorspace::sRGB | Colorspace::RGB => vec![("red", 'R'), ("green", 'G'), ("blue", 'B')],
+        _ => {
+            img.set_colorspace(&Colorspace::sRGB)?;
+            vec![("red", 'R'), ("green", 'G'), ("blue", 'B')]
+        }
+    };
+    if img.has_alpha() {
+        channels.push(("alpha", 'A'));
+    }
+    let map: String = channels.iter().map(|&(_, c)| c).collect();
+    let pixels = img.pixels16(&map)?;
+    if pixels.is_empty() {
+        bail!("stats: the image has no pixels.");
+    }
+
+    Ok(ImageStats {
+        colorspace: format!("{:?}", colorspace),
+        sample: (img.width(), img.height()),
+        channels: channels.iter().enumerate().map(|(i, &(name, _))| channel_stats(name, &pixels, i, channels.len())).collect(),
+        total_area_coverage: if colorspace == Colorspace::CMYK {
+            Some(total_area_coverage(&pixels, channels.len()))
+        } else { None }
+    })
+}
+
+pub fn filter(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(stats, args, 0, context, Image);
+    Box::new(img.and_then(|img| {
+        Ok(Box::new(JsonResult::new(&stats(&img)?)?).into())
+    }))
+}
+
+#[test]
+fn test_channel_stats() {
+    let pixels = [0, 10, 65535, 20];
+    let stats = channel_stats("red", &pixels, 0, 2);
+    assert_eq!(stats.min, 0.0);
+    assert_eq!(stats.max, 1.0);
+    assert_eq!(stats.mean, 0.5);
+    assert_eq!(stats.stddev, 0.5);
+    assert_eq!(stats.histogram[0], 1);
+    assert_eq!(stats.histogram[255], 1);
+}
+
+#[test]
+fn test_channel_stats_precision() {
+    // Levels which are equal in 8 bits still have different 16-bit statistics.
+    let stats = channel_stats("gray", &[256, 257], 0, 1);
+    assert_eq!(stats.histogram[1], 2);
+    assert_eq!(stats.min, 256.0 / 65535.0);
+    assert_eq!(stats.max, 257.0 / 65535.0);
+}
+
+#[test]
+fn test_total_area_coverage() {
+    let pixels = [65535, 65535, 65535, 65535, 0, 0, 0, 0];
+    let tac = total_area_coverage(&pixels, 4);
+    assert_eq!(tac.max, 400.0);
+    assert_eq!(tac.mean, 200.0);
+}
+
+#[test]
+fn test_sample_size() {
+    assert_eq!(sample_size(800, 600, 1024), None);
+    assert_eq!(sample_size(4096, 2048, 1024), Some((1024, 512)));
+    assert_eq!(sample_size(3000, 20000, 1024), Some((154, 1024)));
+    assert_eq!(sample_size(100000, 10, 1024), Some((1024, 1)));
+}
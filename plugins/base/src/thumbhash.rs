@@ -0,0 +1,125 @@
+//! Encoder for [ThumbHash](https://evanw.github.io/thumbhash/) placeholders,
+//! ported from the reference JavaScript implementation.
+use std::f64::consts::PI;
+
+/// Both dimensions of the encoded image must be at most this big.
+pub const MAX_SIZE: usize = 100;
+
+// Math.round() from JavaScript, which the reference implementation relies on.
+fn round(value: f64) -> f64 {
+    (value + 0.5).floor()
+}
+
+/// Returns the DC component, the normalized AC components and their scale.
+fn encode_channel(channel: &[f64], width: usize, height: usize, nx: usize, ny: usize) -> (f64, Vec<f64>, f64) {
+    let mut dc = 0.0;
+    let mut ac = vec![];
+    let mut scale = 0.0f64;
+    let mut fx = vec![0.0; width];
+    for cy in 0..ny {
+        let mut cx = 0;
+        while cx * ny < nx * (ny - cy) {
+            let mut f = 0.0;
+            for x in 0..width {
+                fx[x] = (PI / width as f64 * cx as f64 * (x as f64 + 0.5)).cos();
+            }
+            for y in 0..height {
+                let fy = (PI / height as f64 * cy as f64 * (y as f64 + 0.5)).cos();
+                for x in 0..width {
+                    f += channel[x + y * width] * fx[x] * fy;
+                }
+            }
+            f /= (width * height) as f64;
+            if cx > 0 || cy > 0 {
+                ac.push(f);
+                scale = scale.max(f.abs());
+            } else {
+                dc = f;
+            }
+            cx += 1;
+        }
+    }
+    if scale > 0.0 {
+        for f in ac.iter_mut() {
+            *f = 0.5 + 0.5 / scale * *f;
+        }
+    }
+    (dc, ac, scale)
+}
+
+/// Encodes `rgba` (4 bytes per pixel, row by row). The image must not be bigger
+/// than `MAX_SIZE` in any dimension.
+pub fn encode(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    assert!(width <= MAX_SIZE && height <= MAX_SIZE);
+    let pixels = width * height;
+    let (mut avg_r, mut avg_g, mut avg_b, mut avg_a) = (0.0, 0.0, 0.0, 0.0);
+    for pixel in rgba.chunks(4).take(pixels) {
+        let alpha = pixel[3] as f64 / 255.0;
+        avg_r += alpha / 255.0 * pixel[0] as f64;
+        avg_g += alpha / 255.0 * pixel[1] as f64;
+        avg_b += alpha / 255.0 * pixel[2] as f64;
+        avg_a += alpha;
+    }
+    if avg_a > 0.0 {
+        avg_r /= avg_a;
+        avg_g /= avg_a;
+        avg_b /= avg_a;
+    }
+
+    let has_alpha = avg_a < pixels as f64;
+    let l_limit = if has_alpha { 5.0 } else { 7.0 };
+    let max_dimension = width.max(height) as f64;
+    let lx = round(l_limit * width as f64 / max_dimension).max(1.0) as usize;
+    let ly = round(l_limit * height as f64 / max_dimension).max(1.0) as usize;
+    let (mut l, mut p, mut q, mut a) = (vec![], vec![], vec![], vec![]);
+    for pixel in rgba.chunks(4).take(pixels) {
+        let alpha = pixel[3] as f64 / 255.0;
+        let r = avg_r * (1.0 - alpha) + alpha / 255.0 * pixel[0] as f64;
+        let g = avg_g * (1.0 - alpha) + alpha / 255.0 * pixel[1] as f64;
+        let b = avg_b * (1.0 - alpha) + alpha / 255.0 * pixel[2] as f64;
+        l.push((r + g + b) / 3.0);
+        p.push((r + g) / 2.0 - b);
+        q.push(r - g);
+        a.push(alpha);
+    }
+
+    let (l_dc, l_ac, l_scale) = encode_channel(&l, width, height, lx.max(3), ly.max(3));
+    let (p_dc, p_ac, p_scale) = encode_channel(&p, width, height, 3, 3);
+    let (q_dc, q_ac, q_scale) = encode_channel(&q, width, height, 3, 3);
+    let is_landscape = width > height;
+    let header24 = round(63.0 * l_dc) as u32
+        | (round(31.5 + 31.5 * p_dc) as u32) << 6
+        | (round(31.5 + 31.5 * q_dc) as u32) << 12
+        | (round(31.0 * l_scale) as u32) << 18
+        | (has_alpha as u32) << 23;
+    let header16 = (if is_landscape { ly } else { lx }) as u32
+        | (round(63.0 * p_scale) as u32) << 3
+        | (round(63.0 * q_scale) as u32) << 9
+        | (is_landscape as u32) << 15;
+    let mut hash = vec![
+        (header24 & 255) as u8, ((header24 >> 8) & 255) as u8, (header24 >> 16) as u8,
+        (header16 & 255) as u8, (header16 >> 8) as u8
+    ];
+
+    let mut channels = vec![l_ac, p_ac, q_ac];
+    if has_alpha {
+        let (a_dc, a_ac, a_scale) = encode_channel(&a, width, height, 5, 5);
+        hash.push(round(15.0 * a_dc) as u8 | (round(15.0 * a_scale) as u8) << 4);
+        channels.push(a_ac);
+    }
+    let ac_start = hash.len();
+    for (index, f) in channels.iter().flat_map(|ac| ac.iter()).enumerate() {
+        if index % 2 == 0 {
+            hash.push(0);
+        }
+        hash[ac_start + index / 2] |= (round(15.0 * f) as u8) << ((index & 1) << 2);
+    }
+    hash
+}
+
+#[test]
+fn test_opaque_hash_length() {
+    let rgba: Vec<u8> = (0..4 * 4).flat_map(|_| vec![128, 128, 128, 255]).collect();
+    // A 5 byte header followed by 27 luminance and 2 * 5 chroma AC components, two per byte.
+    assert_eq!(encode(4, 4, &rgba).len(), 5 + 19);
+}
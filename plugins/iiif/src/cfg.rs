@@ -0,0 +1,19 @@
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IiifConfig {
+    /// Path under which the IIIF front-end is mounted, e.g. `/iiif`.
+    pub prefix: String,
+    /// Prepended to identifiers before they're passed to `download`, e.g. `archive:`.
+    pub source: String,
+    /// Public url of the prefix (e.g. `https://images.example.com/iiif`), used as the base
+    /// of ids in info.json. It has to be configured, because request headers like `Host`
+    /// are controlled by the client.
+    pub base_url: String,
+    /// Name of a cache to store the results in.
+    pub cache: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Config {
+    #[serde(default)]
+    pub iiif: Option<IiifConfig>,
+}
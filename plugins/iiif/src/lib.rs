@@ -0,0 +1,329 @@
+//! A front-end implementing the [IIIF Image API 3.0](https://iiif.io/api/image/3.0/).
+//!
+//! Requests under the configured prefix are translated into regular filter trees,
+//! so that they use the same downloads, caches and image operations as native urls.
+#[macro_use] extern crate imaginator_common as imaginator;
+extern crate imaginator_plugins_base as base;
+extern crate hyper;
+extern crate futures;
+#[macro_use] extern crate failure;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate urlencoding;
+#[macro_use] extern crate lazy_static;
+
+use std::rc::Rc;
+use std::collections::HashMap;
+use futures::{future, Future as FutureTrait};
+use hyper::{Request, Response};
+use imaginator::prelude::*;
+use imaginator::filter::{Args, Future, Context, Filter, FilterArg, SizeUnit};
+use imaginator::img::Filter as FilterType;
+use imaginator::frontend::{apply, bad_request, serve};
+#[cfg(test)] use imaginator::frontend::filter_chain;
+use imaginator::cfg::config;
+use base::output::JsonResult;
+
+pub mod cfg;
+pub use cfg::Config;
+
+lazy_static! {
+    /// Filters used by the trees built from IIIF urls, which aren't available in native urls.
+    static ref PRIVATE_FILTERS: FilterMap = {
+        let mut map: FilterMap = HashMap::new();
+        map.insert("iiif-info", &info);
+        map.insert("iiif-square", &square);
+        map.insert("iiif-size", &scale);
+        map
+    };
+}
+
+pub fn plugin() -> PluginInformation {
+    PluginInformation::new(HashMap::new()).with_middleware(&middleware)
+}
+
+/// A parsed IIIF request.
+#[derive(Debug, Clone)]
+pub enum IiifRequest {
+    /// The source image and its (still url-encoded) identifier.
+    Info(Filter, String),
+    Image(Filter),
+}
+
+fn numbers(input: &str, count: usize) -> Result<Vec<f32>, Error> {
+    let numbers = input.split(',').map(|n| n.parse::<f32>()).collect::<Result<Vec<_>, _>>()
+        .map_err(|_| bad_request(format!("Invalid numbers: {}", input)))?;
+    if numbers.len() != count {
+        return Err(bad_request(format!("Expected {} numbers, got: {}", count, input)));
+    }
+    Ok(numbers)
+}
+
+fn region(img: Filter, region: &str) -> Result<Filter, Error> {
+    Ok(match region {
+        "full" => img,
+        "square" => apply(img, "iiif-square", vec![]),
+        _ if region.starts_with("pct:") => {
+            let n = numbers(&region[4..], 4)?;
+            apply(img, "crop", vec![
+                FilterArg::Float(n[0] / 100.0, SizeUnit::Width),
+                FilterArg::Float(n[1] / 100.0, SizeUnit::Height),
+                FilterArg::Float(n[2] / 100.0, SizeUnit::Width),
+                FilterArg::Float(n[3] / 100.0, SizeUnit::Height),
+            ])
+        },
+        _ => {
+            let n = numbers(region, 4)?;
+            apply(img, "crop", n.iter().map(|&v| FilterArg::Int(v as isize, SizeUnit::None)).collect())
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scale {
+    Max,
+    Percent(f32),
+    /// Width and height, either of which may be 0 to keep the aspect ratio.
+    Exact(usize, usize),
+    /// Fit within the given width and height, keeping the aspect ratio (`!w,h`).
+    Fit(usize, usize),
+}
+
+/// The size parameter of an image request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Size {
+    scale: Scale,
+    /// Whether the `^` prefix allows the result to be bigger than the region.
+    upscale: bool,
+}
+
+impl Size {
+    pub fn parse(size: &str) -> Result<Size, Error> {
+        let invalid = || bad_request(format!("Invalid size: {}", size));
+        let upscale = size.starts_with('^');
+        let spec = if upscale { &size[1..] } else { size };
+        let scale = if spec == "max" {
+            Scale::Max
+        } else if spec.starts_with("pct:") {
+            let pct = numbers(&spec[4..], 1)?[0];
+            if pct <= 0.0 || (pct > 100.0 && !upscale) {
+                return Err(invalid());
+            }
+            Scale::Percent(pct / 100.0)
+        } else {
+            let (fit, spec) = if spec.starts_with('!') { (true, &spec[1..]) } else { (false, spec) };
+            let mut parts = spec.splitn(2, ',');
+            let mut dimension = || -> Result<usize, Error> {
+                match parts.next() {
+                    Some("") => Ok(0),
+                    Some(n) => n.parse().map_err(|_| invalid()),
+                    None => Err(invalid()),
+                }
+            };
+            let (w, h) = (dimension()?, dimension()?);
+            if (w == 0 && h == 0) || (fit && (w == 0 || h == 0)) {
+                return Err(invalid());
+            }
+            if fit { Scale::Fit(w, h) } else { Scale::Exact(w, h) }
+        };
+        Ok(Size { scale, upscale })
+    }
+
+    /// Size of a `width`x`height` region after scaling. Sizes bigger than the region
+    /// are rejected unless upscaling was requested, except for `!w,h`, which is capped instead.
+    pub fn apply(&self, width: usize, height: usize) -> Result<(usize, usize), Error> {
+        let (w, h) = (width as f64, height as f64);
+        let (new_w, new_h) = match self.scale {
+            Scale::Max => (w, h),
+            Scale::Percent(pct) => (w * pct as f64, h * pct as f64),
+            Scale::Exact(0, new_h) => (w * new_h as f64 / h, new_h as f64),
+            Scale::Exact(new_w, 0) => (new_w as f64, h * new_w as f64 / w),
+            Scale::Exact(new_w, new_h) => (new_w as f64, new_h as f64),
+            Scale::Fit(max_w, max_h) => {
+                let ratio = (max_w as f64 / w).min(max_h as f64 / h);
+                let ratio = if self.upscale { ratio } else { ratio.min(1.0) };
+                (w * ratio, h * ratio)
+            }
+        };
+        let (new_w, new_h) = ((new_w.round() as usize).max(1), (new_h.round() as usize).max(1));
+        if !self.upscale && (new_w > width || new_h > height) {
+            return Err(bad_request(format!("Size {}x{} is bigger than the region ({}x{}), use ^ to allow upscaling.", new_w, new_h, width, height)));
+        }
+        Ok((new_w, new_h))
+    }
+}
+
+fn size(img: Filter, size: &str) -> Result<Filter, Error> {
+    let parsed = Size::parse(size)?;
+    if parsed == (Size { scale: Scale::Max, upscale: false }) {
+        return Ok(img);
+    }
+    Ok(apply(img, "iiif-size", vec![FilterArg::String(size.to_owned())]))
+}
+
+fn rotation(mut img: Filter, rotation: &str) -> Result<Filter, Error> {
+    let degrees = if rotation.starts_with('!') {
+        img = apply(img, "flop", vec![]);
+        &rotation[1..]
+    } else { rotation };
+    let degrees: f32 = degrees.parse().map_err(|_| bad_request(format!("Invalid rotation: {}", rotation)))?;
+    if degrees < 0.0 || degrees > 360.0 {
+        return Err(bad_request(format!("Invalid rotation: {}", rotation)));
+    }
+    if degrees == 0.0 || degrees == 360.0 {
+        return Ok(img);
+    }
+    Ok(apply(img, "rotate", vec![FilterArg::Float(degrees, SizeUnit::None)]))
+}
+
+fn quality(img: Filter, quality: &str) -> Result<Filter, Error> {
+    Ok(match quality {
+        "default" | "color" => img,
+        "gray" => apply(img, "grayscale", vec![]),
+        "bitonal" => apply(img, "threshold", vec![FilterArg::Int(50, SizeUnit::None)]),
+        _ => return Err(bad_request(format!("Unsupported quality: {}", quality)))
+    })
+}
+
+fn format(img: Filter, format: &str) -> Result<Filter, Error> {
+    match format {
+        "jpg" | "png" | "tif" | "pdf" => Ok(apply(img, "format", vec![FilterArg::String(format.to_owned())])),
+        _ => Err(bad_request(format!("Unsupported format: {}", format)))
+    }
+}
+
+/// Parses the part of the path following the prefix: either `{id}/info.json`
+/// or `{id}/{region}/{size}/{rotation}/{quality}.{format}`.
+/// Identifiers are resolved relative to `source`, which is usually a `domains` prefix.
+pub fn parse(path: &str, source: &str) -> Result<IiifRequest, Error> {
+    let parts: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    let id = urlencoding::decode(parts[0]).map_err(|_| bad_request(format!("Invalid identifier: {}", parts[0])))?;
+    if id.is_empty() {
+        return Err(bad_request("Missing identifier.".to_owned()));
+    }
+    let img = Filter {
+        name: "download".to_owned(),
+        args: vec![FilterArg::String(format!("{}{}", source, id))]
+    };
+    match parts.len() {
+        2 if parts[1] == "info.json" => Ok(IiifRequest::Info(img, parts[0].to_owned())),
+        5 => {
+            let mut quality_format = parts[4].splitn(2, '.');
+            let quality_part = quality_format.next().unwrap();
+            let format_part = quality_format.next().ok_or_else(|| bad_request("Missing format.".to_owned()))?;
+            let img = region(img, parts[1])?;
+            let img = size(img, parts[2])?;
+            let img = rotation(img, parts[3])?;
+            let img = quality(img, quality_part)?;
+            Ok(IiifRequest::Image(format(img, format_part)?))
+        },
+        _ => Err(bad_request(format!("Invalid IIIF url: {}", path)))
+    }
+}
+
+fn middleware(request: Rc<Request>, context: Context) -> Box<FutureTrait<Item=Option<Response>, Error=Error>> {
+    let cfg = match config::<Config>().unwrap().iiif {
+        Some(ref cfg) => cfg,
+        None => return Box::new(future::ok(None))
+    };
+    let mut context = context;
+    context.private_filters = Some(&*PRIVATE_FILTERS);
+    serve(&request, &cfg.prefix, context, cfg.cache.as_ref(), |path| match parse(path, &cfg.source)? {
+        IiifRequest::Image(filter) => Ok(filter),
+        IiifRequest::Info(img, id) => {
+            Ok(apply(img, "iiif-info", vec![FilterArg::String(format!("{}/{}", cfg.base_url.trim_end_matches('/'), id))]))
+        }
+    })
+}
+
+#[derive(Serialize, Debug)]
+struct ImageInformation {
+    #[serde(rename = "@context")]
+    context: &'static str,
+    id: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    protocol: &'static str,
+    profile: &'static str,
+    width: usize,
+    height: usize,
+    #[serde(rename = "extraFormats")]
+    extra_formats: Vec<&'static str>,
+    #[serde(rename = "extraQualities")]
+    extra_qualities: Vec<&'static str>,
+}
+
+/// iiif-info(img, id): the info.json document of the image.
+pub fn info(context: &mut Context, args: &Args) -> Box<Future> {
+    let img = arg_type!(iiif_info, args, 0, context, Image);
+    let id = arg_type!(iiif_info, args, 1, String);
+    Box::new(img.and_then(move |img| {
+        let info = ImageInformation {
+            context: "http://iiif.io/api/image/3/context.json",
+            id,
+            kind: "ImageService3",
+            protocol: "http://iiif.io/api/image",
+            profile: "level1",
+            width: img.width(),
+            height: img.height(),
+            extra_formats: vec!["tif", "pdf"],
+            extra_qualities: vec!["color", "gray", "bitonal"],
+        };
+        Ok(Box::new(JsonResult::new(&info)?).into())
+    }))
+}
+
+image_filter!(scale(img: Image, spec: String) {
+    let (w, h) = Size::parse(&spec)?.apply(img.width(), img.height())?;
+    if (w, h) != (img.width(), img.height()) {
+        img.resize(w, h, &FilterType::default())?;
+    }
+});
+
+image_filter!(square(img: Image) {
+    let (w, h) = (img.width(), img.height());
+    let size = w.min(h);
+    img.crop(((w - size) / 2) as isize, ((h - size) / 2) as isize, size, size)?;
+    img.reset_page()?;
+});
+
+#[test]
+fn test_parse_image_request() {
+    match parse("/abc.jpg/pct:10,10,50,50/!200,100/!90/gray.png", "archive:").unwrap() {
+        IiifRequest::Image(filter) => assert_eq!(
+            filter_chain(&filter),
+            vec!["download", "crop", "iiif-size", "flop", "rotate", "grayscale", "format"]
+        ),
+        request => panic!("Unexpected request: {:?}", request)
+    }
+}
+
+#[test]
+fn test_parse_info_request() {
+    match parse("/folder%2Fabc.jpg/info.json", "archive:").unwrap() {
+        IiifRequest::Info(filter, id) => {
+            assert_eq!(id, "folder%2Fabc.jpg");
+            match filter.args[0] {
+                FilterArg::String(ref url) => assert_eq!(url, "archive:folder/abc.jpg"),
+                _ => panic!("download should get a url")
+            }
+        },
+        request => panic!("Unexpected request: {:?}", request)
+    }
+}
+
+#[test]
+fn test_size() {
+    let size = |spec: &str, w, h| Size::parse(spec).and_then(|size| size.apply(w, h)).ok();
+    assert_eq!(size("max", 400, 300), Some((400, 300)));
+    assert_eq!(size("200,", 400, 300), Some((200, 150)));
+    assert_eq!(size("pct:50", 400, 300), Some((200, 150)));
+    assert_eq!(size("!100,100", 400, 300), Some((100, 75)));
+    // Without ^, the region can't be enlarged, and !w,h is capped at the region size.
+    assert_eq!(size("800,", 400, 300), None);
+    assert_eq!(size("^800,", 400, 300), Some((800, 600)));
+    assert_eq!(size("!800,800", 400, 300), Some((400, 300)));
+    assert_eq!(size("^!800,800", 400, 300), Some((800, 600)));
+    assert!(Size::parse("pct:150").is_err());
+    assert!(Size::parse("^pct:150").is_ok());
+}
@@ -0,0 +1,20 @@
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImgproxyConfig {
+    /// Path under which imgproxy-style urls are served, e.g. `/imgproxy`.
+    pub prefix: String,
+    /// Prepended to source urls before they're passed to `download`.
+    #[serde(default)]
+    pub source: String,
+    /// Hex-encoded signing key. Without it, only `insecure` urls are accepted.
+    pub key: Option<String>,
+    /// Hex-encoded signing salt.
+    pub salt: Option<String>,
+    /// Name of a cache to store the results in.
+    pub cache: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Config {
+    #[serde(default)]
+    pub imgproxy: Option<ImgproxyConfig>,
+}
@@ -0,0 +1,223 @@
+//! A front-end accepting [imgproxy](https://docs.imgproxy.net/)-style urls:
+//! `/{signature}/{option:args}/.../plain/{url}@{ext}` or `/{signature}/{option:args}/.../{base64 url}.{ext}`.
+//!
+//! Supported options are `resize`, `size`, `resizing_type`, `width`, `height`, `quality`,
+//! `format`, `blur`, `trim` and `rotate`, along with their short forms. `gravity`, `enlarge`,
+//! `extend` and `dpr` are accepted and ignored; anything else is rejected.
+extern crate imaginator_common as imaginator;
+extern crate hyper;
+extern crate futures;
+#[macro_use] extern crate failure;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate urlencoding;
+extern crate crypto;
+extern crate base64;
+
+use std::rc::Rc;
+use std::collections::HashMap;
+use futures::{future, Future as FutureTrait};
+use hyper::{Request, Response};
+use crypto::hmac::Hmac;
+use crypto::mac::{Mac, MacResult};
+use crypto::sha2::Sha256;
+use imaginator::prelude::*;
+use imaginator::filter::{Context, Filter, FilterArg, SizeUnit};
+use imaginator::frontend::{apply, bad_request, serve};
+#[cfg(test)] use imaginator::frontend::filter_chain;
+use imaginator::cfg::config;
+
+pub mod cfg;
+pub use cfg::Config;
+
+pub fn plugin() -> PluginInformation {
+    PluginInformation::new(HashMap::new()).with_middleware(&middleware)
+}
+
+fn hex(input: &str) -> Result<Vec<u8>, Error> {
+    if input.len() % 2 != 0 {
+        bail!("Invalid hex string: {}", input);
+    }
+    (0..input.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&input[i..i + 2], 16).map_err(|_| format_err!("Invalid hex string: {}", input)))
+        .collect()
+}
+
+fn check_signature(signature: &str, path: &str, key: &[u8], salt: &[u8]) -> bool {
+    let signature = match base64::decode_config(signature.trim_end_matches('='), base64::URL_SAFE_NO_PAD) {
+        Ok(signature) => signature,
+        Err(_) => return false
+    };
+    let mut hmac = Hmac::new(Sha256::new(), key);
+    hmac.input(salt);
+    hmac.input(path.as_bytes());
+    hmac.result() == MacResult::new(&signature)
+}
+
+#[derive(Default)]
+struct Options {
+    resizing_type: Option<String>,
+    width: isize,
+    height: isize,
+    quality: Option<isize>,
+    format: Option<String>,
+    blur: Option<isize>,
+    trim: Option<isize>,
+    rotate: Option<isize>,
+}
+
+fn int(option: &str, value: Option<&&str>) -> Result<isize, Error> {
+    value.and_then(|v| v.parse().ok()).ok_or_else(|| bad_request(format!("Invalid arguments of option: {}", option)))
+}
+
+impl Options {
+    fn set(&mut self, segment: &str) -> Result<(), Error> {
+        let mut args: Vec<&str> = segment.split(':').collect();
+        let name = args.remove(0);
+        match name {
+            "resize" | "rs" => {
+                self.resizing_type = args.get(0).map(|t| t.to_string());
+                self.width = int(segment, args.get(1))?;
+                self.height = int(segment, args.get(2))?;
+            },
+            "size" | "s" => {
+                self.width = int(segment, args.get(0))?;
+                self.height = int(segment, args.get(1))?;
+            },
+            "resizing_type" | "rt" => self.resizing_type = args.get(0).map(|t| t.to_string()),
+            "width" | "w" => self.width = int(segment, args.get(0))?,
+            "height" | "h" => self.height = int(segment, args.get(0))?,
+            "quality" | "q" => self.quality = Some(int(segment, args.get(0))?),
+            "format" | "f" | "ext" => self.format = args.get(0).map(|f| f.to_string()),
+            "blur" | "bl" => self.blur = Some(int(segment, args.get(0))?),
+            "trim" | "t" => self.trim = Some(int(segment, args.get(0))?),
+            "rotate" | "rot" => self.rotate = Some(int(segment, args.get(0))?),
+            "gravity" | "g" | "enlarge" | "el" | "extend" | "ex" | "dpr" => {},
+            _ => return Err(bad_request(format!("Unsupported option: {}", name)))
+        }
+        Ok(())
+    }
+}
+
+fn format_name(ext: &str) -> Result<&'static str, Error> {
+    Ok(match ext {
+        "jpg" | "jpeg" => "jpg",
+        "png" => "png",
+        "tiff" | "tif" => "tiff",
+        "pdf" => "pdf",
+        _ => return Err(bad_request(format!("Unsupported format: {}", ext)))
+    })
+}
+
+/// Parses the part of the path following the prefix.
+pub fn parse(path: &str, source: &str, key: Option<(&[u8], &[u8])>) -> Result<Filter, Error> {
+    let path = path.trim_start_matches('/');
+    let mut split = path.splitn(2, '/');
+    let signature = split.next().unwrap();
+    let rest = split.next().ok_or_else(|| bad_request("Missing source url.".to_owned()))?;
+    let valid = match key {
+        Some((key, salt)) => check_signature(signature, &format!("/{}", rest), key, salt),
+        None => signature == "insecure" || signature == "_"
+    };
+    if !valid {
+        return Err(bad_request("Invalid signature.".to_owned()));
+    }
+
+    let mut options = Options::default();
+    let mut segments = rest.split('/');
+    let mut encoded = vec![];
+    let mut url = None;
+    while let Some(segment) = segments.next() {
+        if segment == "plain" {
+            let plain = segments.collect::<Vec<_>>().join("/");
+            let (plain, ext) = match plain.rfind('@') {
+                Some(at) => (plain[..at].to_owned(), Some(plain[at + 1..].to_owned())),
+                None => (plain, None)
+            };
+            let plain = urlencoding::decode(&plain).map_err(|_| bad_request(format!("Invalid source url: {}", plain)))?;
+            url = Some((plain, ext));
+            break;
+        } else if segment.contains(':') && encoded.is_empty() {
+            options.set(segment)?;
+        } else {
+            encoded.push(segment);
+        }
+    }
+    let (url, ext) = match url {
+        Some(url) => url,
+        None => {
+            let encoded = encoded.concat();
+            let (encoded, ext) = match encoded.rfind('.') {
+                Some(dot) => (&encoded[..dot], Some(encoded[dot + 1..].to_owned())),
+                None => (&encoded[..], None)
+            };
+            let url = base64::decode_config(encoded.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()
+                .and_then(|url| String::from_utf8(url).ok())
+                .ok_or_else(|| bad_request("Invalid source url.".to_owned()))?;
+            (url, ext)
+        }
+    };
+    if url.is_empty() {
+        return Err(bad_request("Missing source url.".to_owned()));
+    }
+
+    let int_arg = |v: isize| FilterArg::Int(v, SizeUnit::None);
+    let mut img = Filter {
+        name: "download".to_owned(),
+        args: vec![FilterArg::String(format!("{}{}", source, url))]
+    };
+    if let Some(threshold) = options.trim {
+        img = apply(img, "trim", vec![int_arg(threshold * 100 / 255)]);
+    }
+    if options.width != 0 || options.height != 0 {
+        let dimensions = vec![int_arg(options.width), int_arg(options.height)];
+        let both = options.width != 0 && options.height != 0;
+        img = match options.resizing_type.as_ref().map(String::as_str) {
+            Some("force") => apply(img, "resize", dimensions),
+            Some("fill") if both => apply(img, "fill", dimensions),
+            None | Some("fit") | Some("auto") | Some("fill") => apply(img, "fit-in", dimensions),
+            Some(other) => return Err(bad_request(format!("Unsupported resizing type: {}", other)))
+        };
+    }
+    if let Some(degrees) = options.rotate {
+        img = apply(img, "rotate", vec![int_arg(degrees)]);
+    }
+    if let Some(sigma) = options.blur {
+        img = apply(img, "blur", vec![int_arg(sigma)]);
+    }
+    if let Some(quality) = options.quality {
+        img = apply(img, "quality", vec![int_arg(quality)]);
+    }
+    if let Some(ext) = options.format.or(ext) {
+        img = apply(img, "format", vec![FilterArg::String(format_name(&ext)?.to_owned())]);
+    }
+    Ok(img)
+}
+
+fn middleware(request: Rc<Request>, context: Context) -> Box<FutureTrait<Item=Option<Response>, Error=Error>> {
+    let cfg = match config::<Config>().unwrap().imgproxy {
+        Some(ref cfg) => cfg,
+        None => return Box::new(future::ok(None))
+    };
+    let key = match (cfg.key.as_ref().map(|k| hex(k)), cfg.salt.as_ref().map(|s| hex(s))) {
+        (Some(Ok(key)), Some(Ok(salt))) => Some((key, salt)),
+        (Some(Ok(key)), None) => Some((key, vec![])),
+        (None, _) => None,
+        _ => return Box::new(future::err(format_err!("Invalid imgproxy key or salt.")))
+    };
+    let key = key.as_ref().map(|&(ref key, ref salt)| (&key[..], &salt[..]));
+    serve(&request, &cfg.prefix, context, cfg.cache.as_ref(), |path| parse(path, &cfg.source, key))
+}
+
+#[test]
+fn test_parse() {
+    let filter = parse("/insecure/rs:fill:300:200/q:80/plain/http%3A%2F%2Fexample.com%2Fa.jpg@png", "", None).unwrap();
+    assert_eq!(filter_chain(&filter), vec!["download", "fill", "quality", "format"]);
+    let filter = parse("/_/w:300/aHR0cDovL2V4YW1wbGUuY29tL2EuanBn.jpg", "", None).unwrap();
+    assert_eq!(filter_chain(&filter), vec!["download", "fit-in", "format"]);
+    assert_eq!(filter.args.get(0).and_then(|a| match *a {
+        FilterArg::Img(ref download) => Some(format!("{}", download.args[0])),
+        _ => None
+    }), Some("http://example.com/a.jpg".to_owned()));
+    assert!(parse("/insecure/foo:1/plain/a.jpg", "", None).is_err());
+}
@@ -0,0 +1,21 @@
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThumborConfig {
+    /// Path under which Thumbor-style urls are served, e.g. `/thumbor`.
+    pub prefix: String,
+    /// Prepended to image paths before they're passed to `download`, e.g. `s3:`.
+    #[serde(default)]
+    pub source: String,
+    /// Security key used to sign urls. Without it, only `unsafe` urls are accepted.
+    pub key: Option<String>,
+    /// Whether `unsafe` urls are accepted even though a key is configured.
+    #[serde(default)]
+    pub allow_unsafe: bool,
+    /// Name of a cache to store the results in.
+    pub cache: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Config {
+    #[serde(default)]
+    pub thumbor: Option<ThumborConfig>,
+}
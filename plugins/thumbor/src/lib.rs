@@ -0,0 +1,187 @@
+//! A front-end accepting [Thumbor](https://thumbor.readthedocs.io/)-style urls:
+//! `/{unsafe|signature}/[trim/][AxB:CxD/][fit-in/][-]Ex[-]F/[halign/][valign/][smart/][filters:...]/image`.
+//!
+//! Alignment and smart cropping are accepted, but crops are always centered.
+extern crate imaginator_common as imaginator;
+extern crate hyper;
+extern crate futures;
+extern crate failure;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate urlencoding;
+extern crate crypto;
+extern crate base64;
+
+use std::rc::Rc;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Split;
+use futures::{future, Future as FutureTrait};
+use hyper::{Request, Response};
+use crypto::hmac::Hmac;
+use crypto::mac::{Mac, MacResult};
+use crypto::sha1::Sha1;
+use imaginator::prelude::*;
+use imaginator::filter::{Context, Filter, FilterArg, SizeUnit};
+use imaginator::frontend::{apply, bad_request, serve};
+#[cfg(test)] use imaginator::frontend::filter_chain;
+use imaginator::cfg::config;
+
+pub mod cfg;
+pub use cfg::Config;
+
+pub fn plugin() -> PluginInformation {
+    PluginInformation::new(HashMap::new()).with_middleware(&middleware)
+}
+
+fn check_signature(signature: &str, path: &str, key: &str) -> bool {
+    let signature = match base64::decode_config(signature, base64::URL_SAFE) {
+        Ok(signature) => signature,
+        Err(_) => return false
+    };
+    let mut hmac = Hmac::new(Sha1::new(), key.as_bytes());
+    hmac.input(path.as_bytes());
+    hmac.result() == MacResult::new(&signature)
+}
+
+fn int(input: &str) -> Option<isize> {
+    if input.is_empty() { Some(0) } else { input.parse().ok() }
+}
+
+/// Parses `AxB:CxD` into (left, top, right, bottom).
+fn crop_box(segment: &str) -> Option<(isize, isize, isize, isize)> {
+    let mut corners = segment.splitn(2, ':');
+    let mut corner = || -> Option<(isize, isize)> {
+        let mut coords = corners.next()?.splitn(2, 'x');
+        Some((coords.next()?.parse().ok()?, coords.next()?.parse().ok()?))
+    };
+    let (left, top) = corner()?;
+    let (right, bottom) = corner()?;
+    Some((left, top, right, bottom))
+}
+
+/// Parses `[-]Ex[-]F` into a width and height, negative values meaning a flip.
+fn size(segment: &str) -> Option<(isize, isize)> {
+    if segment == "orig" {
+        return Some((0, 0));
+    }
+    let mut dimensions = segment.splitn(2, 'x');
+    let w = dimensions.next()?;
+    let h = dimensions.next()?;
+    let dimension = |d: &str| if d.starts_with('-') { int(&d[1..]).map(|v| -v) } else { int(d) };
+    Some((dimension(w)?, dimension(h)?))
+}
+
+fn filters(mut img: Filter, segment: &str) -> Result<Filter, Error> {
+    for filter in segment["filters:".len()..].split(':').filter(|f| !f.is_empty()) {
+        let open = filter.find('(').ok_or_else(|| bad_request(format!("Invalid filter: {}", filter)))?;
+        let name = &filter[..open];
+        let args: Vec<&str> = filter[open + 1..].trim_end_matches(')').split(',').filter(|a| !a.is_empty()).collect();
+        let arg = |i: usize| -> Result<isize, Error> {
+            args.get(i).and_then(|a| a.parse().ok()).ok_or_else(|| bad_request(format!("Invalid arguments of filter: {}", filter)))
+        };
+        let int_arg = |v: isize| FilterArg::Int(v, SizeUnit::None);
+        img = match name {
+            "quality" => apply(img, "quality", vec![int_arg(arg(0)?)]),
+            "format" => match args.get(0) {
+                Some(&"jpeg") | Some(&"png") => apply(img, "format", vec![FilterArg::String(args[0].to_owned())]),
+                _ => return Err(bad_request(format!("Unsupported format: {}", filter)))
+            },
+            "grayscale" => apply(img, "grayscale", vec![]),
+            "brightness" => apply(img, "brightness-contrast", vec![int_arg(arg(0)?), int_arg(0)]),
+            "contrast" => apply(img, "brightness-contrast", vec![int_arg(0), int_arg(arg(0)?)]),
+            "blur" => apply(img, "blur", vec![int_arg(arg(0)?)]),
+            "rotate" => apply(img, "rotate", vec![int_arg(arg(0)?)]),
+            _ => return Err(bad_request(format!("Unsupported filter: {}", name)))
+        };
+    }
+    Ok(img)
+}
+
+/// Consumes the next segment if it's accepted.
+fn take<'a>(segments: &mut Peekable<Split<'a, char>>, accept: &Fn(&str) -> bool) -> Option<&'a str> {
+    match segments.peek() {
+        Some(segment) if accept(segment) => segments.next(),
+        _ => None
+    }
+}
+
+/// Parses the part of the path following the prefix.
+pub fn parse(path: &str, source: &str, key: Option<&str>, allow_unsafe: bool) -> Result<Filter, Error> {
+    let path = path.trim_start_matches('/');
+    let mut split = path.splitn(2, '/');
+    let signature = split.next().unwrap();
+    let rest = split.next().ok_or_else(|| bad_request("Missing image.".to_owned()))?;
+    let valid = match key {
+        _ if signature == "unsafe" => allow_unsafe || key.is_none(),
+        Some(key) => check_signature(signature, rest, key),
+        None => false
+    };
+    if !valid {
+        return Err(bad_request("Invalid signature.".to_owned()));
+    }
+
+    let mut segments = rest.split('/').peekable();
+    let trim = take(&mut segments, &|s| s == "trim" || s.starts_with("trim:")).is_some();
+    let crop = take(&mut segments, &|s| crop_box(s).is_some()).and_then(crop_box);
+    let fit_in = take(&mut segments, &|s| s.ends_with("fit-in")).is_some();
+    let size = take(&mut segments, &|s| size(s).is_some()).and_then(size);
+    take(&mut segments, &|s| s == "left" || s == "right" || s == "center");
+    take(&mut segments, &|s| s == "top" || s == "bottom" || s == "middle");
+    take(&mut segments, &|s| s == "smart");
+    let filter_spec = take(&mut segments, &|s| s.starts_with("filters:"));
+    let image = segments.collect::<Vec<_>>().join("/");
+    let image = urlencoding::decode(&image).map_err(|_| bad_request(format!("Invalid image: {}", image)))?;
+    if image.is_empty() {
+        return Err(bad_request("Missing image.".to_owned()));
+    }
+
+    let mut img = Filter {
+        name: "download".to_owned(),
+        args: vec![FilterArg::String(format!("{}{}", source, image))]
+    };
+    if trim {
+        img = apply(img, "trim", vec![]);
+    }
+    if let Some((left, top, right, bottom)) = crop {
+        let args = vec![left, top, right - left, bottom - top];
+        img = apply(img, "crop", args.into_iter().map(|v| FilterArg::Int(v, SizeUnit::None)).collect());
+    }
+    if let Some((w, h)) = size {
+        let dimensions = vec![FilterArg::Int(w.abs(), SizeUnit::None), FilterArg::Int(h.abs(), SizeUnit::None)];
+        if fit_in && (w != 0 || h != 0) {
+            img = apply(img, "fit-in", dimensions);
+        } else if w != 0 && h != 0 {
+            img = apply(img, "fill", dimensions);
+        } else if w != 0 || h != 0 {
+            img = apply(img, "resize", dimensions);
+        }
+        if w < 0 {
+            img = apply(img, "flop", vec![]);
+        }
+        if h < 0 {
+            img = apply(img, "flip", vec![]);
+        }
+    }
+    if let Some(spec) = filter_spec {
+        img = filters(img, spec)?;
+    }
+    Ok(img)
+}
+
+fn middleware(request: Rc<Request>, context: Context) -> Box<FutureTrait<Item=Option<Response>, Error=Error>> {
+    let cfg = match config::<Config>().unwrap().thumbor {
+        Some(ref cfg) => cfg,
+        None => return Box::new(future::ok(None))
+    };
+    serve(&request, &cfg.prefix, context, cfg.cache.as_ref(), |path| {
+        parse(path, &cfg.source, cfg.key.as_ref().map(String::as_str), cfg.allow_unsafe)
+    })
+}
+
+#[test]
+fn test_parse() {
+    let filter = parse("/unsafe/trim/10x20:110x220/-300x200/smart/filters:quality(80):grayscale()/img/a.jpg", "s3:", None, false).unwrap();
+    assert_eq!(filter_chain(&filter), vec!["download", "trim", "crop", "fill", "flop", "quality", "grayscale"]);
+    assert!(parse("/unsafe/300x200/img.jpg", "s3:", Some("key"), false).is_err());
+}
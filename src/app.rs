@@ -3,14 +3,19 @@ use futures::{future, Future};
 use tokio_core::reactor::{Handle, Remote};
 use std::collections::HashMap;
 use failure::Error;
-use regex::Regex;
 use chrono::prelude::*;
 use crate::cfg::CONFIG;
 use hyper;
 use crate::url;
+use crate::query;
+use crate::srcset;
 use crate::imaginator::filter::{self, FilterResult};
+use crate::imaginator::img;
 use futures::future::IntoFuture;
 use std::rc::Rc;
+use std::cell::RefCell;
+use std::time::Duration;
+use crate::imaginator::timeout::with_timeout;
 
 type FilterMap = HashMap<&'static str, &'static (Fn(&mut filter::Context, &filter::Args) -> Box<filter::Future> + Sync)>;
 lazy_static! {
@@ -31,65 +36,54 @@ pub struct App {
     pub tokio_core: Handle,
 }
 
-fn apply_alias_args(mut filter: filter::Filter, args: &filter::Args) -> Result<filter::Filter, Error> {
-    lazy_static! {
-        static ref RE_ARG: Regex = Regex::new(r"\{(\d+)\}").unwrap();
-    }
-    let mut new_args = Vec::with_capacity(filter.args.len());
-    for arg in filter.args.into_iter() {
-        match arg {
-            filter::FilterArg::Img(filter) => new_args.push(filter::FilterArg::Img(apply_alias_args(filter, args)?)),
-            filter::FilterArg::String(ref s) => if let Some(m) = RE_ARG.captures(s) {
-                let arg_num: usize = m[1].parse().unwrap();
-                if arg_num >= args.len() {
-                    bail!("Not enough arguments passed to {}. Expected at least {}, but you passed {}.", filter.name, arg_num+1, args.len());
-                }
-                new_args.push(args.get(arg_num).unwrap().clone())
-            } else {
-                // This clone() will potentially be unnecessary when non-lexical lifetimes are
-                // introduced.
-                new_args.push(arg.clone())
-            },
-            _ => new_args.push(arg)
-        }
-    }
-    filter.args = new_args;
-    Ok(filter)
-}
-
-fn apply_filter_aliases(mut filter: filter::Filter) -> Result<filter::Filter, Error> {
-    let mut new_args = Vec::with_capacity(filter.args.len());
-    for arg in filter.args.into_iter() {
-        match arg {
-            filter::FilterArg::Img(filter) => new_args.push(filter::FilterArg::Img(apply_filter_aliases(filter)?)),
-            _ => new_args.push(arg)
-        }
-    }
-    filter.args = new_args;
-    if let Some(value) = CONFIG.aliases.get(&filter.name) {
-        let new_filter = apply_alias_args(url::parse(value)?, &filter.args)?;
-        Ok(new_filter)
-    } else {
-        if CONFIG.allow_builtin_filters {
-            Ok(filter)
-        } else {
-            bail!("no such filter: {}", filter.name);
-        }
+pub fn new_context(remote: &Remote, request_headers: &Rc<hyper::Headers>) -> filter::Context {
+    filter::Context {
+        filters: &FILTERS,
+        private_filters: None,
+        aliases: &CONFIG.aliases,
+        allow_builtin_filters: CONFIG.allow_builtin_filters,
+        remote: remote.clone(),
+        request_headers: request_headers.clone(),
+        log_filters_header: &CONFIG.log_filters_header,
+        steps: Default::default(),
+        filter_log: Default::default(),
+        response_headers: Rc::new(RefCell::new(HashMap::new()))
     }
 }
 
-pub fn exec_from_url(remote: &Remote, url: &str) -> Box<Future<Item = (HashMap<String, String>, Box<FilterResult>), Error = Error>> {
-    let filter = match url::parse(&url).map_err(Error::from).and_then(apply_filter_aliases) {
+fn exec(context: &mut filter::Context, filter: Result<filter::Filter, Error>) -> Box<Future<Item = Box<FilterResult>, Error = Error>> {
+    let filter = match filter {
+        // srcset signs urls built from its arguments, so it needs them before aliases are expanded.
+        Ok(ref filter) if filter.name == "srcset" => return srcset::filter(context, filter.clone()),
+        filter => filter
+    };
+    let filter = match filter.and_then(|filter| filter::apply_aliases(context, filter)) {
         Ok(filter) => filter,
         Err(e) => return Box::new(future::err(e))
     };
-    let mut context = filter::Context {
-        filters: &FILTERS,
-        remote: remote.clone(),
-        log_filters_header: &CONFIG.log_filters_header,
-        response_headers: HashMap::new()
+    filter::exec_filter(context, &filter)
+}
+
+pub fn exec_from_url(context: &mut filter::Context, url: &str) -> Box<Future<Item = Box<FilterResult>, Error = Error>> {
+    exec(context, url::parse(&url).map_err(Error::from))
+}
+
+pub fn exec_from_query(context: &mut filter::Context, cfg: &query::QueryConfig, query: &str) -> Box<Future<Item = Box<FilterResult>, Error = Error>> {
+    exec(context, query::parse(cfg, query).map_err(Error::from))
+}
+
+/// Applies the `timeout` setting. It's checked between steps, so a single slow image
+/// operation can overrun it, but the response then fails as soon as that step is done.
+fn deadline<T: 'static>(future: Box<Future<Item = T, Error = Error>>, context: &filter::Context, handle: &Handle, url: String) -> Box<Future<Item = T, Error = Error>> {
+    let ms = match CONFIG.timeout {
+        Some(ms) => ms,
+        None => return future
     };
-    Box::new(filter::exec_filter(&mut context, &filter).map(move |result| (context.response_headers, result)))
+    let steps = context.steps.clone();
+    with_timeout(future, Duration::from_millis(ms), handle, move || match steps.current() {
+        Some(step) => format!("in `{}` while processing {}", step, url),
+        None => format!("processing {}", url)
+    })
 }
 
 impl App {
@@ -113,6 +107,8 @@ fn handle_failure(err: failure::Error) -> Result<hyper::Response, hyper::Error>
             .with_header(hyper::header::ContentType::plaintext());
         if err.downcast_ref::<url::UrlParseError>().is_some() {
             response.set_status(hyper::StatusCode::BadRequest);
+        } else if err.downcast_ref::<img::ResourcesExhausted>().is_some() {
+            response.set_status(hyper::StatusCode::ServiceUnavailable);
         } else {
             response.set_status(hyper::StatusCode::InternalServerError);
         }
@@ -131,7 +127,10 @@ impl Service for App {
 
     fn call(&self, req: Request) -> Self::Future {
         let remote = self.tokio_core.remote().clone();
+        let handle = self.tokio_core.clone();
         let mut url = req.uri().path()[1..].to_owned();
+        let query_cfg = CONFIG.query.as_ref().filter(|cfg| req.uri().path() == cfg.prefix);
+        let query = req.uri().query().unwrap_or("").to_owned();
         if let Some(query) = req.uri().query() {
             url.push_str("?");
             url.push_str(query);
@@ -147,15 +146,22 @@ impl Service for App {
             ));
         }
 
+        let headers = Rc::new(req.headers().clone());
         let mut future: Box<Future<Item=Option<hyper::Response>, Error=hyper::Error>> = Box::new(future::ok(None));
         let request = Rc::new(req);
         for plugin in imaginator_plugins::plugins().values() {
             if let Some(middleware) = plugin.middleware {
                 let request = request.clone();
+                let context = new_context(&remote, &headers);
+                let handle = handle.clone();
+                let url = url.clone();
                 future = Box::new(future.and_then(move |response| {
                     match response {
                         Some(_) => Box::new(future::ok(response)) as Box<Future<Item=Option<hyper::Response>, Error=_>>,
-                        None => Box::new(middleware(request).or_else(|err| Some(handle_failure(err).into_future())))
+                        None => {
+                            let response = middleware(request, context.clone());
+                            Box::new(deadline(response, &context, &handle, url).or_else(|err| Some(handle_failure(err).into_future())))
+                        }
                     }
                 }));
             }
@@ -165,19 +171,14 @@ impl Service for App {
             if let Some(r) = response {
                 return Box::new(Ok(r).into_future())
             }
-            Box::new(exec_from_url(&remote, &url).and_then(|(headers, img)| {
-                Ok((headers, img.content_type()?, img.content()?))
-            }).map(|(headers, content_type, body)| {
-                let mut response = Response::new()
-                   .with_header(hyper::header::ContentLength(body.len() as u64))
-                   .with_header(content_type);
-                {
-                    let response_headers = response.headers_mut();
-                    for (name, value) in headers.iter() {
-                        response_headers.set_raw(name.clone(), value.as_str());
-                    }
-                }
-                response.with_body(Rc::try_unwrap(body).unwrap())
+            let mut context = new_context(&remote, &headers);
+            let result = match query_cfg {
+                Some(cfg) => exec_from_query(&mut context, cfg, &query),
+                None => exec_from_url(&mut context, &url)
+            };
+            let result = deadline(result, &context, &handle, url);
+            Box::new(result.and_then(move |result| {
+                filter::into_response(&context, result)
             }).or_else(handle_failure).map(move |response| { // Logging
                 println!("{} {}", log_req, response.status().as_u16());
                 response
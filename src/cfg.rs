@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use serde_yaml;
 use std::any::Any;
 use crate::imaginator::cfg::CONFIG as PLUGIN_CONFIG;
+use crate::query::QueryConfig;
 
 include!(concat!(env!("OUT_DIR"), "/cfg_plugins.rs"));
 
@@ -13,7 +14,13 @@ pub struct Config {
     #[serde(flatten)]
     pub filters: Filters,
     pub allow_builtin_filters: bool,
-    pub log_filters_header: Option<String>
+    pub log_filters_header: Option<String>,
+    #[serde(default)]
+    pub query: Option<QueryConfig>,
+    /// Deadline for handling a request, in milliseconds. It's checked between filters,
+    /// so it can't interrupt a single image operation which takes longer.
+    #[serde(default)]
+    pub timeout: Option<u64>
 }
 
 lazy_static! {
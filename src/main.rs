@@ -25,6 +25,7 @@ extern crate urlencoding;
 
 extern crate imaginator_common as imaginator;
 extern crate imaginator_plugins;
+extern crate imaginator_plugins_base as base;
 
 use std::alloc::System;
 
@@ -36,6 +37,8 @@ static ALLOCATOR: System = System;
 mod cfg;
 mod http;
 mod url;
+mod query;
+mod srcset;
 mod app;
 
 fn main() {
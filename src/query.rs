@@ -0,0 +1,156 @@
+//! A front-end mapping query-string parameters to a filter chain, e.g.
+//! `/img?src=s3:abc.jpg&w=300&h=200&fit=cover&fmt=png`.
+//!
+//! Requests can be signed with a `sig` parameter, which is checked against the rest
+//! of the query string the same way url signatures are.
+use std::collections::HashMap;
+use std::str::FromStr;
+use regex::{Captures, Regex};
+use urlencoding;
+use serde::{de, Serialize, Serializer, Deserialize, Deserializer};
+use failure::Error;
+use crate::url::{check_signature, UrlParseError};
+use crate::imaginator::url::{parse as parse_filter, parse_arg};
+use crate::imaginator::filter::{Filter, FilterArg};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueryConfig {
+    /// Path under which query-string requests are served, e.g. `/img`.
+    pub prefix: String,
+    /// Filter templates chained in order, like filters separated with `:` in urls.
+    /// `{param}` placeholders are replaced with query parameters, and steps using a parameter
+    /// which wasn't passed are skipped, except for the first one, which is required.
+    /// Placeholders can also be used in filter names, to pick one of several aliases.
+    ///
+    /// For example: `["download({src})", "fit-{fit}({w},{h})", "format({fmt})"]`.
+    pub steps: Vec<Step>,
+}
+
+/// A step template, parsed once when the configuration is loaded.
+#[derive(Debug, Clone)]
+pub struct Step {
+    source: String,
+    template: Filter,
+}
+
+impl FromStr for Step {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Step, Error> {
+        Ok(Step { source: source.to_owned(), template: parse_filter(source)? })
+    }
+}
+
+impl Serialize for Step {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Step {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Step, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+lazy_static! {
+    static ref RE_PARAM: Regex = Regex::new(r"\{(\w+)\}").unwrap();
+    static ref RE_NAME: Regex = Regex::new(r"^[\w-]+$").unwrap();
+}
+
+fn substitute(input: &str, params: &HashMap<String, String>) -> Option<String> {
+    let mut missing = false;
+    let result = RE_PARAM.replace_all(input, |captures: &Captures| {
+        match params.get(&captures[1]) {
+            Some(value) => value.clone(),
+            None => {
+                missing = true;
+                String::new()
+            }
+        }
+    }).into_owned();
+    if missing { None } else { Some(result) }
+}
+
+/// Fills placeholders in a step template. Returns `None` if a parameter is missing.
+fn fill(template: &Filter, params: &HashMap<String, String>) -> Result<Option<Filter>, UrlParseError> {
+    let name = match substitute(&template.name, params) {
+        Some(name) => name,
+        None => return Ok(None)
+    };
+    if !RE_NAME.is_match(&name) {
+        return Err(UrlParseError::ParseError(format!("Invalid filter name: {}", name)));
+    }
+    let mut args = Vec::with_capacity(template.args.len());
+    for arg in &template.args {
+        args.push(match *arg {
+            FilterArg::Img(ref filter) => match fill(filter, params)? {
+                Some(filter) => FilterArg::Img(filter),
+                None => return Ok(None)
+            },
+            FilterArg::String(ref s) if RE_PARAM.is_match(s) => match substitute(s, params) {
+                // Parameters can't smuggle in filters of their own.
+                Some(value) => match parse_arg(&value) {
+                    Ok(FilterArg::Img(_)) | Err(_) => return Err(UrlParseError::ParseError(format!("Invalid parameter value: {}", value))),
+                    Ok(arg) => arg
+                },
+                None => return Ok(None)
+            },
+            ref arg => arg.clone()
+        });
+    }
+    Ok(Some(Filter { name, args }))
+}
+
+fn decode(input: &str) -> Result<String, UrlParseError> {
+    urlencoding::decode(&input.replace('+', " ")).map_err(|_| UrlParseError::UrlDecodingError)
+}
+
+pub fn parse(cfg: &QueryConfig, query: &str) -> Result<Filter, UrlParseError> {
+    let mut params = HashMap::new();
+    let mut unsigned = vec![];
+    let mut signature = None;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let mut split = pair.splitn(2, '=');
+        let key = decode(split.next().unwrap())?;
+        let value = decode(split.next().unwrap_or(""))?;
+        if key == "sig" {
+            signature = Some(value);
+        } else {
+            unsigned.push(pair);
+            params.insert(key, value);
+        }
+    }
+    if let Some(signature) = signature {
+        if !check_signature(&signature, &unsigned.join("&")) {
+            return Err(UrlParseError::InvalidSignature);
+        }
+    }
+
+    let mut result: Option<Filter> = None;
+    for step in &cfg.steps {
+        match (fill(&step.template, &params)?, result.take()) {
+            (Some(mut filter), Some(img)) => {
+                filter.args.insert(0, FilterArg::Img(img));
+                result = Some(filter);
+            },
+            (Some(filter), None) => result = Some(filter),
+            (None, None) => return Err(UrlParseError::IncompleteUrl),
+            (None, img) => result = img
+        }
+    }
+    result.ok_or(UrlParseError::IncompleteUrl)
+}
+
+#[test]
+fn test_parse() {
+    let cfg = QueryConfig {
+        prefix: "/img".to_owned(),
+        steps: vec!["download({src})".parse().unwrap(), "fit-{fit}({w},{h})".parse().unwrap(), "format({fmt})".parse().unwrap()]
+    };
+    let filter = parse(&cfg, "src=s3%3Aabc.jpg&w=300&h=200&fit=cover").unwrap();
+    assert_eq!(filter.name, "fit-cover");
+    assert_eq!(format!("{}", filter.args[0]), "download(s3:abc.jpg)");
+    assert!(parse(&cfg, "w=300").is_err());
+    assert!(parse(&cfg, "src=download(x)&w=1&h=1&fit=cover").is_err());
+}
@@ -0,0 +1,146 @@
+//! The `srcset` filter: `srcset(img, alias, widths...)` lists signed urls of `alias(img, width)`
+//! for each requested width, as JSON ready to be put into `srcset` attributes.
+//!
+//! Widths above the intrinsic width of the image are never listed. If any were requested,
+//! the image at its intrinsic width is listed instead.
+//!
+//! `srcset` has to be the outermost filter, because the urls are built from its source
+//! as it was written, before aliases are expanded.
+//!
+//! Only configured aliases can be used, as the server signs whatever it lists: with a builtin
+//! filter, `srcset` would sign urls which `allow_builtin_filters` is supposed to reject.
+use std::collections::HashMap;
+use futures::{future, Future as FutureTrait};
+use failure::Error;
+use crate::imaginator::filter::{self, Context, Filter, Future, FilterArg, FilterResult, SizeUnit};
+use crate::imaginator::img::Image;
+use crate::url::sign;
+use base::output::JsonResult;
+
+/// Pixel densities listed in `density_srcset`, relative to the smallest width.
+const DENSITIES: [usize; 3] = [1, 2, 3];
+
+#[derive(Serialize)]
+struct Candidate {
+    url: String,
+    width: usize,
+}
+
+#[derive(Serialize)]
+struct Srcset {
+    /// Intrinsic width of the source image.
+    width: usize,
+    candidates: Vec<Candidate>,
+    /// Candidates with width descriptors, e.g. `/thumb(...,320) 320w, /thumb(...,640) 640w`.
+    srcset: String,
+    /// Candidates with pixel density descriptors, e.g. `/thumb(...,320) 1x, /thumb(...,640) 2x`.
+    density_srcset: String,
+}
+
+/// Lists the widths to generate, given the requested ones, sorted and deduplicated.
+fn candidate_widths(widths: &[usize], intrinsic: usize) -> Vec<usize> {
+    let mut candidates: Vec<usize> = widths.iter().cloned().filter(|&w| w <= intrinsic).collect();
+    if candidates.len() < widths.len() && !candidates.contains(&intrinsic) {
+        candidates.push(intrinsic);
+    }
+    candidates
+}
+
+/// Percent-encodes everything but unreserved characters and the ones the url syntax needs,
+/// so that e.g. spaces and commas don't split candidates in `srcset` attributes.
+fn encode(url: &str) -> String {
+    let mut encoded = String::with_capacity(url.len());
+    for byte in url.bytes() {
+        match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' | b'(' | b')' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    encoded
+}
+
+/// Rejects anything but a configured alias, so that `srcset` can't be used to sign arbitrary urls.
+fn check_alias(aliases: &HashMap<String, String>, alias: &str) -> Result<(), Error> {
+    if !aliases.contains_key(alias) {
+        bail!("`srcset` requires an alias, and {} isn't one", alias);
+    }
+    Ok(())
+}
+
+/// Executes `srcset(img, alias, widths...)`. Unlike other filters, it gets the filter tree
+/// before aliases are applied, so that the urls it signs are accepted as they are.
+pub fn filter(context: &mut Context, srcset: Filter) -> Box<Future> {
+    let args = srcset.args;
+    let (img, alias) = match (args.get(0), args.get(1)) {
+        (Some(&FilterArg::Img(ref img)), Some(&FilterArg::String(ref alias))) => (img.clone(), alias.clone()),
+        _ => return Box::new(future::err(format_err!("Arguments to `srcset` must be an image and an alias name")))
+    };
+    if let Err(e) = check_alias(context.aliases, &alias) {
+        return Box::new(future::err(e));
+    }
+    let mut widths = Vec::with_capacity(args.len());
+    for (i, arg) in args.iter().enumerate().skip(2) {
+        match *arg {
+            FilterArg::Int(w, SizeUnit::None) | FilterArg::Int(w, SizeUnit::Px) if w > 0 => widths.push(w as usize),
+            _ => return Box::new(future::err(format_err!("Argument {} to `srcset` must be a positive width", i + 1)))
+        }
+    }
+    if widths.is_empty() {
+        return Box::new(future::err(format_err!("`srcset` requires at least one width")));
+    }
+    widths.sort();
+    widths.dedup();
+
+    let expanded: Filter = match filter::apply_aliases(context, img.clone()) {
+        Ok(expanded) => expanded,
+        Err(e) => return Box::new(future::err(e))
+    };
+    Box::new(filter::exec_filter(context, &expanded).and_then(move |result| -> Result<Box<FilterResult>, Error> {
+        // Only the dimensions are needed, so there's no point in decoding the image.
+        let mut image = Image::new(None, result.dpi().ok().map(|dpi| dpi.0))?;
+        image.ping(&*result.content()?)?;
+        let intrinsic = image.width();
+        let url = |w: usize| format!("/{}", encode(&sign(&format!("{}({},{})", alias, img, w))));
+
+        let candidates: Vec<_> = candidate_widths(&widths, intrinsic).into_iter()
+            .map(|width| Candidate { url: url(width), width })
+            .collect();
+        let srcset = candidates.iter()
+            .map(|c| format!("{} {}w", c.url, c.width))
+            .collect::<Vec<_>>().join(", ");
+        let base_width = widths[0].min(intrinsic);
+        let density_srcset = DENSITIES.iter()
+            .filter(|&&d| base_width * d <= intrinsic)
+            .map(|&d| format!("{} {}x", url(base_width * d), d))
+            .collect::<Vec<_>>().join(", ");
+
+        Ok(Box::new(JsonResult::new(&Srcset {
+            width: intrinsic,
+            candidates,
+            srcset,
+            density_srcset,
+        })?).into())
+    }))
+}
+
+#[test]
+fn test_candidate_widths() {
+    assert_eq!(candidate_widths(&[320, 640, 1280], 2000), vec![320, 640, 1280]);
+    assert_eq!(candidate_widths(&[320, 640, 1280], 800), vec![320, 640, 800]);
+    assert_eq!(candidate_widths(&[320, 640], 640), vec![320, 640]);
+    assert_eq!(candidate_widths(&[320], 100), vec![100]);
+}
+
+#[test]
+fn test_encode() {
+    assert_eq!(encode("thumb(download(s3:a b,c.jpg),320)"), "thumb(download(s3:a%20b%2Cc.jpg)%2C320)");
+}
+
+#[test]
+fn test_check_alias() {
+    let mut aliases = HashMap::new();
+    aliases.insert("thumb".to_owned(), "resize({0},{1},0)".to_owned());
+    assert!(check_alias(&aliases, "thumb").is_ok());
+    assert!(check_alias(&aliases, "download").is_err());
+    assert!(check_alias(&aliases, "resize").is_err());
+}
@@ -22,16 +22,14 @@ pub enum UrlParseError {
     UrlDecodingError,
 }
 
-fn check_signature(input: &str, rest: &str) -> bool {
+pub fn check_signature(input: &str, rest: &str) -> bool {
     let sig = match base64::decode_config(input, base64::URL_SAFE) {
         Ok(sig) => sig,
         // One way or another, the signature is invalid
         Err(_) => return false
     };
-    println!("{:?} {:?}", CONFIG.secret, rest);
     if let Some(ref secret) = CONFIG.secret {
         let mut hmac = Hmac::new(Sha1::new(), secret.as_bytes());
-        println!("{}", rest);
         hmac.input(rest.as_bytes());
         hmac.result() == MacResult::new(&sig)
     } else {
@@ -39,6 +37,17 @@ fn check_signature(input: &str, rest: &str) -> bool {
     }
 }
 
+/// Prefixes a url with its signature, if a secret is configured.
+pub fn sign(url: &str) -> String {
+    if let Some(ref secret) = CONFIG.secret {
+        let mut hmac = Hmac::new(Sha1::new(), secret.as_bytes());
+        hmac.input(url.as_bytes());
+        format!("{}/{}", base64::encode_config(hmac.result().code(), base64::URL_SAFE), url)
+    } else {
+        url.to_owned()
+    }
+}
+
 fn is_base64(input: &str) -> bool {
     base64::decode_config(input, base64::URL_SAFE).is_ok()
 }